@@ -1,18 +1,26 @@
-use std::io::ErrorKind;
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind};
 use std::net::UdpSocket;
 use std::sync::mpsc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use mtt_core::world::WorldState;
+use mtt_protocol::capture::PcapWriter;
 use mtt_protocol::clientbound::ClientBound;
-use mtt_protocol::serverbound::{self, ServerBound};
+use mtt_protocol::serverbound::ServerBound;
+use mtt_protocol::ClientConfig;
 use mtt_renderer::Renderer;
 use winit::event::{Event, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
+/// Delay before connecting again when the server suggests it.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 pub struct ClientThread {
     client: mtt_protocol::Client,
     socket: UdpSocket,
+    reconnect_at: Option<Instant>,
 
     serverbound_tx: mpsc::Sender<ServerBound>,
     serverbound_rx: mpsc::Receiver<ServerBound>,
@@ -22,8 +30,8 @@ pub struct ClientThread {
 }
 
 impl ClientThread {
-    pub fn new(address: String) -> Self {
-        let client = mtt_protocol::Client::new();
+    pub fn new(address: String, config: ClientConfig, capture_path: Option<String>) -> Self {
+        let mut client = mtt_protocol::Client::new(config);
 
         let (clientbound_tx, clientbound_rx) = mpsc::channel();
         let (serverbound_tx, serverbound_rx) = mpsc::channel();
@@ -32,9 +40,18 @@ impl ClientThread {
         socket.connect(address).unwrap();
         socket.set_nonblocking(true).unwrap();
 
+        if let Some(path) = capture_path {
+            let file = BufWriter::new(File::create(path).unwrap());
+            let local = socket.local_addr().unwrap();
+            let remote = socket.peer_addr().unwrap();
+
+            client.set_recorder(Some(Box::new(PcapWriter::new(file, local, remote).unwrap())));
+        }
+
         Self {
             client,
             socket,
+            reconnect_at: None,
 
             serverbound_tx,
             serverbound_rx,
@@ -46,24 +63,39 @@ impl ClientThread {
 
     #[must_use]
     fn run(mut self) -> Client {
+        self.client.handle_input(mtt_protocol::Input::Connect).unwrap();
+
         let _ = std::thread::spawn(move || loop {
             let mut buf = [0; 1536];
 
+            // Advance the clock first, so that acks are timestamped accurately
+            self.client
+                .handle_input(mtt_protocol::Input::Tick(Instant::now()))
+                .unwrap();
+
+            if self.reconnect_at.is_some_and(|at| at <= Instant::now()) {
+                self.reconnect_at = None;
+                self.client.handle_input(mtt_protocol::Input::Connect).unwrap();
+            }
+
             // Receive all packets from remote server
             loop {
                 let len = match self.socket.recv(&mut buf) {
                     Ok(len) => len,
-                    Err(err) => match err {
-                        e if e.kind() == ErrorKind::WouldBlock => break,
-                        _ => panic!("{}", err),
-                    },
+                    Err(err) => {
+                        // The server may be down or restarting. If it doesn't
+                        // come back, the connection times out
+                        if err.kind() != ErrorKind::WouldBlock {
+                            eprintln!("Receive failed: {}", err);
+                        }
+                        break;
+                    }
                 };
 
-                println!("Receive: {:?}", &buf[..len]);
-
-                self.client
-                    .handle_input(mtt_protocol::Input::Receive(&buf[..len]))
-                    .unwrap();
+                // Malformed datagrams are dropped instead of tearing down the connection
+                if let Err(err) = self.client.handle_input(mtt_protocol::Input::Receive(&buf[..len])) {
+                    eprintln!("Dropped datagram: {:?}", err);
+                }
             }
 
             // Send all queued packets from client
@@ -84,7 +116,30 @@ impl ClientThread {
                         self.clientbound_tx.send(packet).unwrap();
                     }
                     mtt_protocol::Output::Send(data) => {
-                        self.socket.send(&data).unwrap();
+                        // Lost datagrams are resent if they are reliable
+                        if let Err(err) = self.socket.send(&data) {
+                            eprintln!("Send failed: {}", err);
+                        }
+                    }
+                    mtt_protocol::Output::InvalidPacket(packet) => {
+                        eprintln!(
+                            "Invalid packet {:?} at byte {}: {}",
+                            packet.id, packet.offset, packet.error
+                        );
+                    }
+                    mtt_protocol::Output::StateChanged(state) => {
+                        println!("Connection state: {:?}", state);
+                    }
+                    mtt_protocol::Output::Disconnected(reason) => {
+                        eprintln!("Disconnected: {}", reason);
+
+                        if reason.reconnect() {
+                            eprintln!("Reconnecting in {:?}", RECONNECT_DELAY);
+                            self.reconnect_at = Some(Instant::now() + RECONNECT_DELAY);
+                        }
+                    }
+                    mtt_protocol::Output::PasswordMismatch => {
+                        eprintln!("Passwords don't match");
                     }
                     mtt_protocol::Output::None => {}
                 }
@@ -122,9 +177,17 @@ fn main() {
     let renderer = Renderer::new(window).unwrap();
 
     let address = std::env::args().nth(1).expect("address required");
-    let client_thread = ClientThread::new(address);
+    let player_name = std::env::args().nth(2).unwrap_or_else(|| "mtt".to_string());
+    // Raw traffic is written to a pcap file if requested
+    let capture_path = std::env::var("MTT_CAPTURE").ok();
+    let mut config = ClientConfig::new(player_name);
+    config.password = std::env::var("MTT_PASSWORD").unwrap_or_default();
+    // There's no UI to confirm the password yet
+    config.auto_register = std::env::var_os("MTT_REGISTER").is_some();
+    let client_thread = ClientThread::new(address, config, capture_path);
     let client = client_thread.run();
-    client.send(serverbound::Handshake {});
+
+    let mut world = WorldState::new();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
@@ -140,6 +203,10 @@ fn main() {
                 _ => (),
             },
             Event::MainEventsCleared => {
+                for packet in client.receive() {
+                    packet.apply(&mut world);
+                }
+
                 renderer.render();
             }
             _ => (),
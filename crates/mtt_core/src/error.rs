@@ -0,0 +1,14 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unsupported node definition version: {0}")]
+    UnsupportedNodeDefVersion(u8),
+    #[error("invalid definition of node {id}: {error}")]
+    Node {
+        id: u16,
+        error: mtt_serialize::Error,
+    },
+    #[error(transparent)]
+    Deserialize(#[from] mtt_serialize::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
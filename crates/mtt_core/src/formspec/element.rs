@@ -0,0 +1,440 @@
+use crate::formspec::token::{split_escaped, unescape, RawElement};
+use crate::formspec::FormspecError;
+use glam::{UVec2, Vec2};
+
+/// Position and size of an element, in formspec units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub position: Vec2,
+    pub size: Vec2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
+
+impl Orientation {
+    fn from_name(name: &str) -> Self {
+        // Anything else is treated as horizontal by Minetest too
+        match name.trim() {
+            "vertical" => Orientation::Vertical,
+            _ => Orientation::Horizontal,
+        }
+    }
+}
+
+/// Formspec element. Indices of tabs and dropdown items start at one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    FormspecVersion(u32),
+    Size {
+        size: Vec2,
+        fixed_size: Option<bool>,
+    },
+    /// Elements inside are positioned relative to the container.
+    Container {
+        position: Vec2,
+        elements: Vec<Element>,
+    },
+    ScrollContainer {
+        rect: Rect,
+        /// Name of the scrollbar which controls the container.
+        scrollbar: String,
+        orientation: Orientation,
+        scroll_factor: f32,
+        elements: Vec<Element>,
+    },
+    List {
+        location: String,
+        list: String,
+        position: Vec2,
+        /// Number of columns and rows.
+        slots: UVec2,
+        start: u32,
+    },
+    Button {
+        rect: Rect,
+        name: String,
+        label: String,
+        /// Closes the form when pressed.
+        exit: bool,
+    },
+    Field {
+        /// Fields without a position are placed automatically.
+        rect: Option<Rect>,
+        name: String,
+        label: String,
+        default: String,
+        password: bool,
+    },
+    TextArea {
+        rect: Rect,
+        /// Read-only if empty.
+        name: String,
+        label: String,
+        default: String,
+    },
+    Label {
+        position: Vec2,
+        text: String,
+    },
+    Image {
+        rect: Rect,
+        texture: String,
+        /// Makes the image a 9-slice image.
+        middle: Option<String>,
+    },
+    TabHeader {
+        position: Vec2,
+        /// Size is determined automatically if not set.
+        width: Option<f32>,
+        height: Option<f32>,
+        name: String,
+        captions: Vec<String>,
+        current_tab: u32,
+        transparent: bool,
+        draw_border: bool,
+    },
+    Dropdown {
+        position: Vec2,
+        width: f32,
+        height: Option<f32>,
+        name: String,
+        items: Vec<String>,
+        selected: u32,
+        /// Sends the index of the selected item instead of its text.
+        index_event: bool,
+    },
+    Checkbox {
+        position: Vec2,
+        name: String,
+        label: String,
+        selected: bool,
+    },
+    Scrollbar {
+        rect: Rect,
+        orientation: Orientation,
+        name: String,
+        value: i32,
+    },
+    Style {
+        /// Element names, or element types if `by_type` is set, optionally
+        /// followed by a state, e.g. `btn:hovered`.
+        selectors: Vec<String>,
+        properties: Vec<(String, String)>,
+        by_type: bool,
+    },
+    /// Elements which aren't interpreted.
+    Unknown(RawElement),
+}
+
+/// Interprets a value the way Minetest's `is_yes` does.
+fn parse_bool(value: &str) -> bool {
+    let value = value.trim();
+    match value.parse::<f64>() {
+        Ok(number) => number != 0.0,
+        Err(_) => matches!(value.to_ascii_lowercase().as_str(), "y" | "yes" | "true"),
+    }
+}
+
+impl RawElement {
+    fn argument_count_error(&self) -> FormspecError {
+        FormspecError::ArgumentCount {
+            name: self.name.clone(),
+            offset: self.offset,
+            count: self.args.len(),
+        }
+    }
+
+    fn invalid(&self, what: &'static str, value: &str) -> FormspecError {
+        FormspecError::InvalidValue {
+            name: self.name.clone(),
+            offset: self.offset,
+            what,
+            value: value.to_string(),
+        }
+    }
+
+    fn check_count(&self, min: usize) -> Result<(), FormspecError> {
+        if self.args.len() < min {
+            return Err(self.argument_count_error());
+        }
+
+        Ok(())
+    }
+
+    fn arg(&self, index: usize) -> Result<&str, FormspecError> {
+        self.args
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| self.argument_count_error())
+    }
+
+    /// Optional argument, which may also be left empty.
+    fn optional(&self, index: usize) -> Option<&str> {
+        self.args
+            .get(index)
+            .map(String::as_str)
+            .filter(|arg| !arg.trim().is_empty())
+    }
+
+    fn string(&self, index: usize) -> Result<String, FormspecError> {
+        self.arg(index).map(unescape)
+    }
+
+    fn number(&self, value: &str, what: &'static str) -> Result<f32, FormspecError> {
+        value.trim().parse().map_err(|_| self.invalid(what, value))
+    }
+
+    fn numbers(&self, index: usize, what: &'static str) -> Result<Vec<f32>, FormspecError> {
+        split_escaped(self.arg(index)?, ',')
+            .into_iter()
+            .map(|value| self.number(value, what))
+            .collect()
+    }
+
+    fn vec2(&self, index: usize, what: &'static str) -> Result<Vec2, FormspecError> {
+        match self.numbers(index, what)?[..] {
+            [x, y, ..] => Ok(Vec2::new(x, y)),
+            _ => Err(self.invalid(what, &self.args[index])),
+        }
+    }
+
+    fn rect(&self, index: usize) -> Result<Rect, FormspecError> {
+        Ok(Rect {
+            position: self.vec2(index, "position")?,
+            size: self.vec2(index + 1, "size")?,
+        })
+    }
+
+    fn bool_or(&self, index: usize, default: bool) -> bool {
+        self.optional(index).map_or(default, parse_bool)
+    }
+}
+
+impl TryFrom<&RawElement> for Element {
+    type Error = FormspecError;
+
+    /// Interprets a single element. Container contents are filled in while
+    /// parsing a whole formspec.
+    fn try_from(raw: &RawElement) -> Result<Self, FormspecError> {
+        let element = match raw.name.as_str() {
+            "formspec_version" => Element::FormspecVersion(raw.number(raw.arg(0)?, "version")? as u32),
+            "size" => {
+                // Size may be followed by whether it's fixed
+                let values = split_escaped(raw.arg(0)?, ',');
+                let [width, height, rest @ ..] = &values[..] else {
+                    return Err(raw.invalid("size", &raw.args[0]));
+                };
+
+                Element::Size {
+                    size: Vec2::new(raw.number(width, "size")?, raw.number(height, "size")?),
+                    fixed_size: rest.first().map(|value| parse_bool(value)),
+                }
+            }
+            "container" => Element::Container {
+                position: raw.vec2(0, "position")?,
+                elements: Vec::new(),
+            },
+            "scroll_container" => {
+                raw.check_count(4)?;
+                let scroll_factor = match raw.optional(4) {
+                    Some(value) => raw.number(value, "scroll factor")?,
+                    None => 0.1,
+                };
+
+                Element::ScrollContainer {
+                    rect: raw.rect(0)?,
+                    scrollbar: raw.string(2)?,
+                    orientation: Orientation::from_name(&raw.args[3]),
+                    scroll_factor,
+                    elements: Vec::new(),
+                }
+            }
+            "list" => {
+                raw.check_count(4)?;
+                let slots = raw.vec2(3, "size")?;
+                let start = match raw.optional(4) {
+                    Some(value) => raw.number(value, "starting index")? as u32,
+                    None => 0,
+                };
+
+                Element::List {
+                    location: raw.string(0)?,
+                    list: raw.string(1)?,
+                    position: raw.vec2(2, "position")?,
+                    slots: slots.as_uvec2(),
+                    start,
+                }
+            }
+            "button" | "button_exit" => {
+                raw.check_count(4)?;
+
+                Element::Button {
+                    rect: raw.rect(0)?,
+                    name: raw.string(2)?,
+                    label: raw.string(3)?,
+                    exit: raw.name == "button_exit",
+                }
+            }
+            "field" if raw.args.len() < 5 => {
+                raw.check_count(3)?;
+
+                Element::Field {
+                    rect: None,
+                    name: raw.string(0)?,
+                    label: raw.string(1)?,
+                    default: raw.string(2)?,
+                    password: false,
+                }
+            }
+            "field" | "pwdfield" => {
+                raw.check_count(4)?;
+                let password = raw.name == "pwdfield";
+                let default = if password { String::new() } else { raw.string(4)? };
+
+                Element::Field {
+                    rect: Some(raw.rect(0)?),
+                    name: raw.string(2)?,
+                    label: raw.string(3)?,
+                    default,
+                    password,
+                }
+            }
+            "textarea" => {
+                raw.check_count(5)?;
+
+                Element::TextArea {
+                    rect: raw.rect(0)?,
+                    name: raw.string(2)?,
+                    label: raw.string(3)?,
+                    default: raw.string(4)?,
+                }
+            }
+            "label" => {
+                raw.check_count(2)?;
+
+                Element::Label {
+                    position: raw.vec2(0, "position")?,
+                    text: raw.string(1)?,
+                }
+            }
+            "image" => {
+                raw.check_count(3)?;
+
+                Element::Image {
+                    rect: raw.rect(0)?,
+                    texture: raw.string(2)?,
+                    middle: raw.optional(3).map(unescape),
+                }
+            }
+            "tabheader" => {
+                raw.check_count(4)?;
+
+                // Size is optional, and tells apart the two forms which take
+                // the same number of arguments otherwise
+                let has_size = matches!(raw.args.len(), 5 | 7) || raw.args.len() > 7;
+                let (width, height, first) = if has_size {
+                    match raw.numbers(1, "size")?[..] {
+                        [height] => (None, Some(height), 2),
+                        [width, height, ..] => (Some(width), Some(height), 2),
+                        [] => return Err(raw.invalid("size", &raw.args[1])),
+                    }
+                } else {
+                    (None, None, 1)
+                };
+
+                Element::TabHeader {
+                    position: raw.vec2(0, "position")?,
+                    width,
+                    height,
+                    name: raw.string(first)?,
+                    captions: raw.list(first + 1).unwrap_or_default(),
+                    current_tab: raw.number(raw.arg(first + 2)?, "current tab")? as u32,
+                    transparent: raw.bool_or(first + 3, false),
+                    draw_border: raw.bool_or(first + 4, true),
+                }
+            }
+            "dropdown" => {
+                raw.check_count(5)?;
+                let (width, height) = match raw.numbers(1, "size")?[..] {
+                    [width] => (width, None),
+                    [width, height, ..] => (width, Some(height)),
+                    [] => return Err(raw.invalid("size", &raw.args[1])),
+                };
+
+                Element::Dropdown {
+                    position: raw.vec2(0, "position")?,
+                    width,
+                    height,
+                    name: raw.string(2)?,
+                    items: raw.list(3).unwrap_or_default(),
+                    selected: raw.number(&raw.args[4], "selected index")? as u32,
+                    index_event: raw.bool_or(5, false),
+                }
+            }
+            "checkbox" => {
+                raw.check_count(3)?;
+
+                Element::Checkbox {
+                    position: raw.vec2(0, "position")?,
+                    name: raw.string(1)?,
+                    label: raw.string(2)?,
+                    selected: raw.bool_or(3, false),
+                }
+            }
+            "scrollbar" => {
+                raw.check_count(5)?;
+
+                Element::Scrollbar {
+                    rect: raw.rect(0)?,
+                    orientation: Orientation::from_name(&raw.args[2]),
+                    name: raw.string(3)?,
+                    value: raw.number(&raw.args[4], "value")? as i32,
+                }
+            }
+            "style" | "style_type" => {
+                raw.check_count(2)?;
+                let selectors = raw.list(0).unwrap_or_default();
+                let properties = raw.args[1..]
+                    .iter()
+                    .filter(|property| !property.trim().is_empty())
+                    .map(|property| {
+                        let (key, value) = property
+                            .split_once('=')
+                            .ok_or_else(|| raw.invalid("property", property))?;
+                        Ok((key.trim().to_string(), unescape(value.trim())))
+                    })
+                    .collect::<Result<_, FormspecError>>()?;
+
+                Element::Style {
+                    selectors: selectors.iter().map(|selector| selector.trim().to_string()).collect(),
+                    properties,
+                    by_type: raw.name == "style_type",
+                }
+            }
+            _ => Element::Unknown(raw.clone()),
+        };
+
+        Ok(element)
+    }
+}
+
+impl Element {
+    /// Elements inside a container.
+    pub fn children(&self) -> Option<&[Element]> {
+        match self {
+            Element::Container { elements, .. } | Element::ScrollContainer { elements, .. } => Some(elements),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn children_mut(&mut self) -> Option<&mut Vec<Element>> {
+        match self {
+            Element::Container { elements, .. } | Element::ScrollContainer { elements, .. } => Some(elements),
+            _ => None,
+        }
+    }
+}
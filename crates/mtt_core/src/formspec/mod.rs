@@ -0,0 +1,212 @@
+//! Formspecs, the text format forms and inventory screens are described in.
+
+pub mod element;
+pub mod token;
+
+pub use self::element::{Element, Orientation, Rect};
+pub use self::token::{escape, tokenize, unescape, RawElement};
+
+/// Offsets are in bytes from the start of the formspec.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FormspecError {
+    #[error("element at {offset} isn't terminated")]
+    Unterminated {
+        offset: usize,
+    },
+    #[error("expected an element at {offset}")]
+    ExpectedElement {
+        offset: usize,
+    },
+    #[error("{name} at {offset} can't have {count} arguments")]
+    ArgumentCount {
+        name: String,
+        offset: usize,
+        count: usize,
+    },
+    #[error("invalid {what} of {name} at {offset}: {value:?}")]
+    InvalidValue {
+        name: String,
+        offset: usize,
+        what: &'static str,
+        value: String,
+    },
+    #[error("{name} at {offset} doesn't close a container")]
+    UnmatchedEnd {
+        name: String,
+        offset: usize,
+    },
+    #[error("container at {offset} isn't closed")]
+    Unclosed {
+        offset: usize,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Formspec {
+    pub elements: Vec<Element>,
+}
+
+impl Formspec {
+    /// Parses a whole formspec, failing on the first invalid element.
+    ///
+    /// To skip invalid elements instead, like Minetest does, [`tokenize`]
+    /// the formspec and convert the elements one by one.
+    pub fn parse(source: &str) -> Result<Self, FormspecError> {
+        let mut elements = Vec::new();
+        // Containers which are still open, along with their offsets
+        let mut open: Vec<(Element, usize)> = Vec::new();
+
+        for raw in tokenize(source)? {
+            let element = match raw.name.as_str() {
+                "container_end" | "scroll_container_end" => match (open.pop(), raw.name.as_str()) {
+                    (Some((container @ Element::Container { .. }, _)), "container_end")
+                    | (Some((container @ Element::ScrollContainer { .. }, _)), "scroll_container_end") => container,
+                    _ => {
+                        return Err(FormspecError::UnmatchedEnd {
+                            name: raw.name,
+                            offset: raw.offset,
+                        })
+                    }
+                },
+                _ => {
+                    let element = Element::try_from(&raw)?;
+                    if element.children().is_some() {
+                        open.push((element, raw.offset));
+                        continue;
+                    }
+
+                    element
+                }
+            };
+
+            match open.last_mut() {
+                Some((container, _)) => container.children_mut().unwrap().push(element),
+                None => elements.push(element),
+            }
+        }
+
+        if let Some((_, offset)) = open.first() {
+            return Err(FormspecError::Unclosed { offset: *offset });
+        }
+
+        Ok(Self { elements })
+    }
+
+    /// Version of the formspec syntax, which is 1 if not specified.
+    pub fn version(&self) -> u32 {
+        self.elements
+            .iter()
+            .find_map(|element| match element {
+                Element::FormspecVersion(version) => Some(*version),
+                _ => None,
+            })
+            .unwrap_or(1)
+    }
+
+    /// Iterates over all elements, including the ones inside containers.
+    pub fn iter(&self) -> impl Iterator<Item = &Element> {
+        let mut stack: Vec<&Element> = self.elements.iter().rev().collect();
+        std::iter::from_fn(move || {
+            let element = stack.pop()?;
+            if let Some(children) = element.children() {
+                stack.extend(children.iter().rev());
+            }
+            Some(element)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+
+    fn label(x: f32, y: f32, text: &str) -> Element {
+        Element::Label {
+            position: Vec2::new(x, y),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn escaped_delimiters_are_kept_in_arguments() {
+        let formspec = Formspec::parse(r"label[1,2;a\]b\;c\,d]").unwrap();
+        assert_eq!(formspec.elements, [label(1.0, 2.0, "a]b;c,d")]);
+
+        let formspec = Formspec::parse(r"dropdown[0,0;3;choice;one\,two,three;2]").unwrap();
+        let Element::Dropdown { items, selected, .. } = &formspec.elements[0] else {
+            panic!("expected a dropdown");
+        };
+        assert_eq!(items, &["one,two", "three"]);
+        assert_eq!(*selected, 2);
+    }
+
+    #[test]
+    fn unknown_elements_are_kept_raw() {
+        let formspec = Formspec::parse(r"size[4,3] hypertext[a;b\;c]").unwrap();
+
+        assert_eq!(
+            formspec.elements[1],
+            Element::Unknown(RawElement {
+                name: "hypertext".to_string(),
+                args: vec!["a".to_string(), r"b\;c".to_string()],
+                offset: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn unterminated_elements_are_rejected() {
+        assert_eq!(
+            Formspec::parse("size[4,3]label[0,0;hi"),
+            Err(FormspecError::Unterminated { offset: 9 })
+        );
+        // An escaped bracket doesn't end the element
+        assert_eq!(
+            Formspec::parse(r"label[0,0;hi\]"),
+            Err(FormspecError::Unterminated { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn containers_nest() {
+        let source = concat!(
+            "container[1,2]",
+            "label[0,0;a]",
+            "container[3,4]label[0,0;b]container_end[]",
+            "container_end[]",
+            "label[0,0;c]",
+        );
+        let formspec = Formspec::parse(source).unwrap();
+
+        let inner = Element::Container {
+            position: Vec2::new(3.0, 4.0),
+            elements: vec![label(0.0, 0.0, "b")],
+        };
+        let outer = Element::Container {
+            position: Vec2::new(1.0, 2.0),
+            elements: vec![label(0.0, 0.0, "a"), inner],
+        };
+        assert_eq!(formspec.elements, [outer, label(0.0, 0.0, "c")]);
+        assert_eq!(formspec.iter().count(), 5);
+    }
+
+    #[test]
+    fn container_ends_must_match() {
+        assert_eq!(
+            Formspec::parse("label[0,0;a]container_end[]"),
+            Err(FormspecError::UnmatchedEnd {
+                name: "container_end".to_string(),
+                offset: 12,
+            })
+        );
+        assert!(matches!(
+            Formspec::parse("container[0,0]scroll_container_end[]"),
+            Err(FormspecError::UnmatchedEnd { .. })
+        ));
+        assert_eq!(
+            Formspec::parse("container[0,0]container[1,1]container_end[]"),
+            Err(FormspecError::Unclosed { offset: 0 })
+        );
+    }
+}
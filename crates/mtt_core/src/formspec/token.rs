@@ -0,0 +1,125 @@
+use crate::formspec::FormspecError;
+
+/// Element as written, before its arguments are interpreted.
+///
+/// Arguments are kept escaped, since some of them are lists which are split
+/// at unescaped commas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawElement {
+    pub name: String,
+    pub args: Vec<String>,
+    /// Byte offset of the element in the formspec.
+    pub offset: usize,
+}
+
+impl RawElement {
+    /// Unescaped argument, if there is one at `index`.
+    pub fn text(&self, index: usize) -> Option<String> {
+        self.args.get(index).map(|arg| unescape(arg))
+    }
+
+    /// Argument at `index` split into unescaped values.
+    pub fn list(&self, index: usize) -> Option<Vec<String>> {
+        let arg = self.args.get(index)?;
+        Some(split_escaped(arg, ',').into_iter().map(unescape).collect())
+    }
+}
+
+/// Splits `s` at occurrences of `delimiter` which aren't escaped with a
+/// backslash. Escapes are kept in the parts.
+pub fn split_escaped(s: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Removes backslashes, keeping the characters they escape.
+pub fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Escapes characters which have a meaning in formspecs, so that `s` can be
+/// used as a single argument.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '[' | ']' | ';' | ',') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn parse_element(s: &str, offset: usize) -> Result<Option<RawElement>, FormspecError> {
+    let trimmed = s.trim_start();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+
+    let offset = offset + (s.len() - trimmed.len());
+    let (name, args) = trimmed
+        .split_once('[')
+        .ok_or(FormspecError::ExpectedElement { offset })?;
+
+    let args = if args.is_empty() {
+        Vec::new()
+    } else {
+        split_escaped(args, ';').into_iter().map(str::to_string).collect()
+    };
+
+    Ok(Some(RawElement {
+        name: name.trim_end().to_string(),
+        args,
+        offset,
+    }))
+}
+
+/// Splits a formspec into elements of the form `name[arg;arg,..]`.
+pub fn tokenize(source: &str) -> Result<Vec<RawElement>, FormspecError> {
+    let mut elements = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+
+    for (i, c) in source.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == ']' {
+            elements.extend(parse_element(&source[start..i], start)?);
+            start = i + 1;
+        }
+    }
+
+    let rest = &source[start..];
+    if !rest.trim().is_empty() {
+        return Err(FormspecError::Unterminated {
+            offset: start + (rest.len() - rest.trim_start().len()),
+        });
+    }
+
+    Ok(elements)
+}
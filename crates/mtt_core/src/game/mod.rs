@@ -1,11 +1,11 @@
 pub mod node;
 
 use crate::game::node::{Argb, DrawType, Lighting, Node, Rgb};
-use anyhow::Result;
+use crate::{Error, Result};
 use flate2::read::ZlibDecoder;
 use mtt_serialize::Serialize;
 use std::collections::HashMap;
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
 pub struct Item {}
 
@@ -39,6 +39,12 @@ fn air() -> Node {
             light_source: 0,
         },
         is_ground_content: true,
+        node_dig_prediction: None,
+        leveled_max: None,
+        alpha: None,
+        move_resistance: None,
+        liquid_move_physics: None,
+        post_effect_color_shaded: None,
     }
 }
 
@@ -53,13 +59,13 @@ impl Game {
     pub fn deserialize_nodes(data: &[u8]) -> Result<Game> {
         let mut game = Game::new();
 
-        let mut reader = ZlibDecoder::new(data);
-        let mut data = Vec::new();
-        reader.read_to_end(&mut data)?;
+        let data = mtt_serialize::decompress(ZlibDecoder::new(data))?;
 
         let r = &mut Cursor::new(data);
         let version = u8::deserialize(r)?;
-        anyhow::ensure!(version == 1);
+        if version != 1 {
+            return Err(Error::UnsupportedNodeDefVersion(version));
+        }
 
         let count = u16::deserialize(r)?;
 
@@ -67,14 +73,15 @@ impl Game {
         let _ = u32::deserialize(r)?;
 
         for _ in 0..count {
-            let id = u16::deserialize(r)? as usize;
-            let node = Node::deserialize(r)?;
+            let id = u16::deserialize(r)?;
+            let node = Node::deserialize(r).map_err(|error| Error::Node { id, error })?;
+            let index = id as usize;
 
-            if id >= game.nodes.len() {
-                game.nodes.resize(id + 1, air());
+            if index >= game.nodes.len() {
+                game.nodes.resize(index + 1, air());
             }
 
-            game.nodes[id] = node;
+            game.nodes[index] = node;
         }
 
         Ok(game)
@@ -1,10 +1,9 @@
 use crate::spatial::Aabb;
-use anyhow::Result;
 use bitflags::bitflags;
 use mtt_macros::Serialize;
-use mtt_serialize::Serialize;
+use mtt_serialize::{deserialize_optional, Error, Result, Serialize};
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 
 #[derive(Debug, Clone)]
 pub enum TileAnimation {
@@ -22,8 +21,30 @@ pub enum TileAnimation {
 }
 
 impl Serialize for TileAnimation {
-    fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        unimplemented!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        match self {
+            TileAnimation::None => 0u8.serialize(w),
+            TileAnimation::VerticalFrames {
+                aspect_w,
+                aspect_h,
+                length,
+            } => {
+                1u8.serialize(w)?;
+                aspect_w.serialize(w)?;
+                aspect_h.serialize(w)?;
+                length.serialize(w)
+            }
+            TileAnimation::Sheet {
+                frames_w,
+                frames_h,
+                length,
+            } => {
+                2u8.serialize(w)?;
+                frames_w.serialize(w)?;
+                frames_h.serialize(w)?;
+                length.serialize(w)
+            }
+        }
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
@@ -41,13 +62,18 @@ impl Serialize for TileAnimation {
                 frames_h: u8::deserialize(r)?,
                 length: f32::deserialize(r)?,
             },
-            _ => anyhow::bail!("invalid tile animation type: {}", ty),
+            _ => {
+                return Err(Error::UnknownVariant {
+                    what: "tile animation type",
+                    value: ty as u64,
+                })
+            }
         })
     }
 }
 
 #[derive(Debug, Clone)]
-struct TileFlags(u16);
+pub struct TileFlags(u16);
 
 bitflags! {
     impl TileFlags: u16 {
@@ -67,7 +93,7 @@ pub struct Rgb {
     pub b: u8,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Argb {
     pub a: u8,
     pub r: u8,
@@ -83,8 +109,13 @@ pub enum Alignment {
 }
 
 impl Serialize for Alignment {
-    fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        unimplemented!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        let ty: u8 = match self {
+            Alignment::None => 0,
+            Alignment::World => 1,
+            Alignment::UserDefined => 2,
+        };
+        ty.serialize(w)
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
@@ -93,29 +124,63 @@ impl Serialize for Alignment {
             0 => Alignment::None,
             1 => Alignment::World,
             2 => Alignment::UserDefined,
-            _ => anyhow::bail!("invalid alignment type: {}", ty),
+            _ => {
+                return Err(Error::UnknownVariant {
+                    what: "alignment type",
+                    value: ty as u64,
+                })
+            }
         })
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Tile {
-    name: String,
-    animation: TileAnimation,
-    flags: TileFlags,
-    color: Option<Rgb>,
-    scale: u8,
-    alignment: Alignment,
+    pub name: String,
+    pub animation: TileAnimation,
+    pub flags: TileFlags,
+    pub color: Option<Rgb>,
+    pub scale: u8,
+    pub alignment: Alignment,
 }
 
+/// Tile format version written by `Tile::serialize`.
+const TILE_VERSION: u8 = 6;
+
 impl Serialize for Tile {
-    fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        unimplemented!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        // Optional fields are written only if their flag is set, so the
+        // flags have to agree with the values
+        let mut flags = self.flags.clone();
+        flags.set(TileFlags::HAS_COLOR, self.color.is_some());
+        flags.set(TileFlags::HAS_SCALE, self.scale != 0);
+        flags.set(TileFlags::HAS_ALIGNMENT, !matches!(self.alignment, Alignment::None));
+
+        TILE_VERSION.serialize(w)?;
+        self.name.serialize(w)?;
+        self.animation.serialize(w)?;
+        flags.bits().serialize(w)?;
+        if let Some(color) = &self.color {
+            color.serialize(w)?;
+        }
+        if self.scale != 0 {
+            self.scale.serialize(w)?;
+        }
+        if !matches!(self.alignment, Alignment::None) {
+            self.alignment.serialize(w)?;
+        }
+
+        Ok(())
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let version = u8::deserialize(r)?;
-        anyhow::ensure!(version >= 6, "bad tile version");
+        if version < 6 {
+            return Err(Error::UnsupportedVersion {
+                what: "tile",
+                version: version as u64,
+            });
+        }
 
         let name = String::deserialize(r)?;
         let animation = TileAnimation::deserialize(r)?;
@@ -186,8 +251,8 @@ pub struct Boxes {
 }
 
 impl Serialize for Boxes {
-    fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        unimplemented!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.boxes.serialize(w)
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
@@ -207,6 +272,7 @@ pub struct NodeBoxConnectors {
     right: Boxes,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 pub enum NodeBox {
     Regular,
@@ -226,14 +292,53 @@ pub enum NodeBox {
     },
 }
 
+/// Node box format version written by `NodeBox::serialize`.
+const NODEBOX_VERSION: u8 = 6;
+
 impl Serialize for NodeBox {
-    fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        todo!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        NODEBOX_VERSION.serialize(w)?;
+        match self {
+            NodeBox::Regular => 0u8.serialize(w),
+            NodeBox::Fixed(boxes) => {
+                1u8.serialize(w)?;
+                boxes.serialize(w)
+            }
+            NodeBox::WallMounted { top, bottom, side } => {
+                2u8.serialize(w)?;
+                top.serialize(w)?;
+                bottom.serialize(w)?;
+                side.serialize(w)
+            }
+            NodeBox::Leveled(boxes) => {
+                3u8.serialize(w)?;
+                boxes.serialize(w)
+            }
+            NodeBox::Connected {
+                connected,
+                connectors,
+                disconnected_connectors,
+                disconnected,
+                disconnected_sides,
+            } => {
+                4u8.serialize(w)?;
+                connected.serialize(w)?;
+                connectors.serialize(w)?;
+                disconnected_connectors.serialize(w)?;
+                disconnected.serialize(w)?;
+                disconnected_sides.serialize(w)
+            }
+        }
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let version = u8::deserialize(r)?;
-        anyhow::ensure!(version >= 6, "bad nodebox version");
+        if version < 6 {
+            return Err(Error::UnsupportedVersion {
+                what: "nodebox",
+                version: version as u64,
+            });
+        }
         let ty = u8::deserialize(r)?;
         Ok(match ty {
             0 => NodeBox::Regular,
@@ -251,7 +356,12 @@ impl Serialize for NodeBox {
                 disconnected: Boxes::deserialize(r)?,
                 disconnected_sides: Boxes::deserialize(r)?,
             },
-            _ => anyhow::bail!("unknown nodebox type: {}", ty),
+            _ => {
+                return Err(Error::UnknownVariant {
+                    what: "nodebox type",
+                    value: ty as u64,
+                })
+            }
         })
     }
 }
@@ -294,8 +404,28 @@ pub enum DrawType {
 }
 
 impl Serialize for DrawType {
-    fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        todo!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        let ty: u8 = match self {
+            DrawType::Normal => 0,
+            DrawType::AirLike => 1,
+            DrawType::Liquid => 2,
+            DrawType::FlowingLiquid => 3,
+            DrawType::GlassLike => 4,
+            DrawType::AllFaces => 5,
+            DrawType::AllFacesOptional => 6,
+            DrawType::TorchLike => 7,
+            DrawType::SignLike => 8,
+            DrawType::PlantLike => 9,
+            DrawType::FenceLike => 10,
+            DrawType::RailLike => 11,
+            DrawType::NodeBox => 12,
+            DrawType::GlassLikeFramed => 13,
+            DrawType::FireLike => 14,
+            DrawType::GlassLikeFramedOptional => 15,
+            DrawType::Mesh => 16,
+            DrawType::PlantLikeRooted => 17,
+        };
+        ty.serialize(w)
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
@@ -319,7 +449,12 @@ impl Serialize for DrawType {
             15 => DrawType::GlassLikeFramedOptional,
             16 => DrawType::Mesh,
             17 => DrawType::PlantLikeRooted,
-            _ => anyhow::bail!("unknown DrawType: {}", ty),
+            _ => {
+                return Err(Error::UnknownVariant {
+                    what: "draw type",
+                    value: ty as u64,
+                })
+            }
         })
     }
 }
@@ -345,6 +480,14 @@ pub struct Node {
     pub leveled: u8,
     pub lighting: Lighting,
     pub is_ground_content: bool,
+    // Fields below were added in later versions and are missing when talking
+    // to older servers
+    pub node_dig_prediction: Option<String>,
+    pub leveled_max: Option<u8>,
+    pub alpha: Option<u8>,
+    pub move_resistance: Option<u8>,
+    pub liquid_move_physics: Option<bool>,
+    pub post_effect_color_shaded: Option<bool>,
     // pub interaction: Interaction,
     // pub liquid: Liquid,
     // pub node_box: NodeBox,
@@ -354,17 +497,29 @@ pub struct Node {
 }
 
 impl Serialize for Node {
+    /// Interaction, liquid, box and sound properties are dropped while
+    /// deserializing, so a definition can't be written back.
     fn serialize<W: Write>(&self, _w: &mut W) -> Result<()> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            what: "node definition",
+        })
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         // length of serialized NodeDef
         let nodedef_size = u16::deserialize(r)?;
-        let r = &mut r.take(nodedef_size as u64);
+
+        // Newer servers append fields which aren't known yet, so the whole
+        // definition is consumed up front and the rest is ignored
+        let r = &mut Cursor::new(mtt_serialize::read_bytes(r, nodedef_size as usize)?);
 
         let version = u8::deserialize(r)?;
-        anyhow::ensure!(version >= 13);
+        if version < 13 {
+            return Err(Error::UnsupportedVersion {
+                what: "node definition",
+                version: version as u64,
+            });
+        }
 
         let name = String::deserialize(r)?;
 
@@ -386,7 +541,13 @@ impl Serialize for Node {
         let visual_scale = f32::deserialize(r)?;
 
         let tile_count = u8::deserialize(r)?;
-        anyhow::ensure!(tile_count == 6);
+        if tile_count != 6 {
+            return Err(Error::Unexpected {
+                what: "tile count",
+                expected: 6,
+                actual: tile_count as u64,
+            });
+        }
 
         let mut tiles = Vec::new();
         for _ in 0..tile_count {
@@ -400,7 +561,13 @@ impl Serialize for Node {
         }
 
         let special_tile_count = u8::deserialize(r)?;
-        anyhow::ensure!(special_tile_count == 6);
+        if special_tile_count != 6 {
+            return Err(Error::Unexpected {
+                what: "special tile count",
+                expected: 6,
+                actual: special_tile_count as u64,
+            });
+        }
 
         let mut tiles_special = Vec::new();
         for _ in 0..special_tile_count {
@@ -434,12 +601,12 @@ impl Serialize for Node {
         let _ = u8::deserialize(r)?;
         let _ = u8::deserialize(r)?;
 
-        // TODO: new, optional attributes
-        let _node_dig_prediction = String::deserialize(r)?;
-        let _ = u8::deserialize(r)?;
-        let _ = u8::deserialize(r)?;
-        let _ = u8::deserialize(r)?;
-        let _ = u8::deserialize(r)?;
+        let node_dig_prediction = deserialize_optional(r)?;
+        let leveled_max = deserialize_optional(r)?;
+        let alpha = deserialize_optional(r)?;
+        let move_resistance = deserialize_optional(r)?;
+        let liquid_move_physics = deserialize_optional(r)?;
+        let post_effect_color_shaded = deserialize_optional(r)?;
 
         Ok(Self {
             name,
@@ -461,6 +628,12 @@ impl Serialize for Node {
             leveled,
             lighting,
             is_ground_content,
+            node_dig_prediction,
+            leveled_max,
+            alpha,
+            move_resistance,
+            liquid_move_physics,
+            post_effect_color_shaded,
             // interaction,
             // liquid,
             // node_box,
@@ -0,0 +1,228 @@
+use mtt_serialize::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::str::FromStr;
+
+const META_START: char = '\x01';
+const META_KV_DELIM: char = '\x02';
+const META_PAIR_DELIM: char = '\x03';
+
+/// Whether a string has to be quoted to be read back as a single word.
+fn needs_quoting(s: &str) -> bool {
+    s.is_empty()
+        || s.chars()
+            .any(|c| c.is_control() || !c.is_ascii() || c == ' ' || c == '"')
+}
+
+fn write_json_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\x08' => f.write_str("\\b")?,
+            '\x0c' => f.write_str("\\f")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_ascii_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+fn write_word(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    if needs_quoting(s) {
+        write_json_string(f, s)
+    } else {
+        f.write_str(s)
+    }
+}
+
+/// Reads a JSON string from the start of `s`, returning it along with the
+/// rest of the input.
+///
+/// Minetest escapes strings byte by byte, so a `\u` escape stands for a single
+/// byte of UTF-8 rather than a code point.
+fn read_json_string(s: &str) -> Result<(String, &str)> {
+    let invalid = || Error::Invalid { what: "JSON string" };
+
+    let mut chars = s.strip_prefix('"').ok_or_else(invalid)?.char_indices();
+    let mut bytes = Vec::new();
+    while let Some((i, c)) = chars.next() {
+        let escaped = match c {
+            '"' => return Ok((String::from_utf8_lossy(&bytes).into_owned(), &s[i + 2..])),
+            '\\' => chars.next().ok_or(Error::Truncated)?.1,
+            c => {
+                bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+        };
+
+        match escaped {
+            'b' => bytes.push(b'\x08'),
+            'f' => bytes.push(b'\x0c'),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            'u' => {
+                let digits: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                let value = u16::from_str_radix(&digits, 16).map_err(|_| invalid())?;
+                bytes.push(value as u8);
+            }
+            c => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+
+    Err(Error::Truncated)
+}
+
+/// Reads a word which may be quoted, returning it along with the rest of the
+/// input.
+fn read_word(s: &str) -> Result<(String, &str)> {
+    let s = s.trim_start();
+    if s.starts_with('"') {
+        return read_json_string(s);
+    }
+
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    Ok((s[..end].to_string(), &s[end..]))
+}
+
+/// Key-value pairs attached to an item stack, e.g. a custom `description`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemMetadata {
+    pub fields: BTreeMap<String, String>,
+}
+
+impl ItemMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(String::as_str)
+    }
+
+    fn parse(s: &str) -> Self {
+        let fields = match s.strip_prefix(META_START) {
+            Some(pairs) => pairs
+                .split_terminator(META_PAIR_DELIM)
+                .map(|pair| {
+                    let (key, value) = pair.split_once(META_KV_DELIM).unwrap_or((pair, ""));
+                    (key.to_string(), value.to_string())
+                })
+                .collect(),
+            // Metadata used to be a single unnamed string
+            None if !s.is_empty() => BTreeMap::from([(String::new(), s.to_string())]),
+            None => BTreeMap::new(),
+        };
+
+        Self { fields }
+    }
+}
+
+impl fmt::Display for ItemMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut data = String::from(META_START);
+        for (key, value) in &self.fields {
+            data.push_str(key);
+            data.push(META_KV_DELIM);
+            data.push_str(value);
+            data.push(META_PAIR_DELIM);
+        }
+
+        write_word(f, &data)
+    }
+}
+
+/// Items in a single inventory slot, in the `name count wear metadata` form
+/// Minetest uses in inventories and formspecs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemStack {
+    pub name: String,
+    pub count: u16,
+    /// Tool wear, from zero for a new tool up to 65535.
+    pub wear: u16,
+    pub metadata: ItemMetadata,
+}
+
+impl ItemStack {
+    pub fn new(name: impl Into<String>, count: u16) -> Self {
+        Self {
+            name: name.into(),
+            count,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty() || self.count == 0
+    }
+
+    /// Description shown to players, if it's overridden by the metadata.
+    pub fn description(&self) -> Option<&str> {
+        self.metadata.get("description")
+    }
+}
+
+impl FromStr for ItemStack {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Invalid { what: "item stack" };
+
+        let (name, rest) = read_word(s)?;
+
+        // Trailing fields are left out when they have their default values
+        let (count, rest) = read_word(rest)?;
+        let count = match count.as_str() {
+            "" => 1,
+            count => count.parse().map_err(|_| invalid())?,
+        };
+        let (wear, rest) = read_word(rest)?;
+        let wear = match wear.as_str() {
+            "" => 0,
+            wear => wear.parse().map_err(|_| invalid())?,
+        };
+        let (metadata, _) = read_word(rest)?;
+        let metadata = ItemMetadata::parse(&metadata);
+
+        let stack = Self {
+            name,
+            count,
+            wear,
+            metadata,
+        };
+        if stack.is_empty() {
+            return Ok(Self::default());
+        }
+
+        Ok(stack)
+    }
+}
+
+impl fmt::Display for ItemStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        write_word(f, &self.name)?;
+
+        let has_metadata = !self.metadata.is_empty();
+        let has_wear = self.wear != 0 || has_metadata;
+        if self.count != 1 || has_wear {
+            write!(f, " {}", self.count)?;
+        }
+        if has_wear {
+            write!(f, " {}", self.wear)?;
+        }
+        if has_metadata {
+            write!(f, " {}", self.metadata)?;
+        }
+
+        Ok(())
+    }
+}
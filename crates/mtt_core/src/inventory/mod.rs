@@ -0,0 +1,269 @@
+pub mod item;
+
+pub use self::item::{ItemMetadata, ItemStack};
+
+use mtt_serialize::{Error, Result, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::Lines;
+
+#[derive(Debug, Clone, Default)]
+pub struct InventoryList {
+    pub name: String,
+    /// Number of columns the list is shown in, zero if not set.
+    pub width: u32,
+    pub items: Vec<ItemStack>,
+}
+
+/// Named lists of item stacks, e.g. `main` and `craft` of a player.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub lists: Vec<InventoryList>,
+}
+
+impl Inventory {
+    pub fn list(&self, name: &str) -> Option<&InventoryList> {
+        self.lists.iter().find(|list| list.name == name)
+    }
+
+    pub fn list_mut(&mut self, name: &str) -> Option<&mut InventoryList> {
+        self.lists.iter_mut().find(|list| list.name == name)
+    }
+
+    /// Replaces the lists with the ones in `update`. Lists which aren't
+    /// mentioned by the update are removed.
+    pub fn apply(&mut self, update: &InventoryUpdate) {
+        let mut old_lists = std::mem::take(&mut self.lists);
+        let mut take_old = |name: &str| {
+            let index = old_lists.iter().position(|list| list.name == name)?;
+            Some(old_lists.swap_remove(index))
+        };
+
+        for list_update in &update.lists {
+            match list_update {
+                ListUpdate::Keep(name) => self.lists.extend(take_old(name)),
+                ListUpdate::Changed {
+                    name,
+                    size,
+                    width,
+                    items,
+                } => {
+                    let mut list = take_old(name).unwrap_or_else(|| InventoryList {
+                        name: name.clone(),
+                        ..InventoryList::default()
+                    });
+                    list.width = *width;
+                    list.items.resize(*size, ItemStack::default());
+
+                    // Slots which aren't listed are emptied, like Minetest does
+                    let mut updates = items.iter();
+                    for slot in &mut list.items {
+                        match updates.next() {
+                            Some(SlotUpdate::Keep) => {}
+                            Some(SlotUpdate::Set(item)) => *slot = item.clone(),
+                            None => *slot = ItemStack::default(),
+                        }
+                    }
+
+                    self.lists.push(list);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SlotUpdate {
+    Keep,
+    Set(ItemStack),
+}
+
+#[derive(Debug, Clone)]
+pub enum ListUpdate {
+    /// List didn't change since the last update.
+    Keep(String),
+    Changed {
+        name: String,
+        size: usize,
+        width: u32,
+        /// Slots past the end are emptied.
+        items: Vec<SlotUpdate>,
+    },
+}
+
+/// Inventory in the text format the server sends it in. Only lists and
+/// items which changed since the last update may be included.
+#[derive(Debug, Clone, Default)]
+pub struct InventoryUpdate {
+    pub lists: Vec<ListUpdate>,
+}
+
+fn split_keyword(line: &str) -> (&str, &str) {
+    let line = line.trim();
+    line.split_once(' ').unwrap_or((line, ""))
+}
+
+fn parse_list(name: &str, size: usize, lines: &mut Lines) -> Result<ListUpdate> {
+    let mut width = 0;
+    let mut items = Vec::new();
+
+    for line in lines {
+        let item = match split_keyword(line) {
+            ("Width", width_str) => {
+                width = width_str.parse().map_err(|_| Error::Invalid {
+                    what: "inventory list width",
+                })?;
+                continue;
+            }
+            ("Item", item) => SlotUpdate::Set(item.parse()?),
+            ("Empty", _) => SlotUpdate::Set(ItemStack::default()),
+            ("Keep", _) => SlotUpdate::Keep,
+            ("EndInventoryList" | "end", _) => {
+                return Ok(ListUpdate::Changed {
+                    name: name.to_string(),
+                    size,
+                    width,
+                    items,
+                });
+            }
+            _ => continue,
+        };
+
+        if items.len() == size {
+            return Err(Error::Invalid {
+                what: "inventory list (too many items)",
+            });
+        }
+        items.push(item);
+    }
+
+    Err(Error::Truncated)
+}
+
+impl InventoryUpdate {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        let mut lists = Vec::new();
+
+        while let Some(line) = lines.next() {
+            match split_keyword(line) {
+                ("", _) => {}
+                ("List", args) => {
+                    let (name, size) = args.split_once(' ').ok_or(Error::Invalid { what: "inventory list" })?;
+                    let size: usize = size.trim().parse().map_err(|_| Error::Invalid {
+                        what: "inventory list size",
+                    })?;
+
+                    let limit = mtt_serialize::limits().max_elements;
+                    if size > limit {
+                        return Err(Error::LimitExceeded {
+                            what: "inventory list size",
+                            len: size,
+                            limit,
+                        });
+                    }
+
+                    lists.push(parse_list(name, size, &mut lines)?);
+                }
+                ("KeepList", name) => lists.push(ListUpdate::Keep(name.to_string())),
+                ("EndInventory" | "end", _) => return Ok(Self { lists }),
+                _ => return Err(Error::Invalid { what: "inventory line" }),
+            }
+        }
+
+        Err(Error::Truncated)
+    }
+}
+
+impl From<&Inventory> for InventoryUpdate {
+    fn from(inventory: &Inventory) -> Self {
+        let lists = inventory
+            .lists
+            .iter()
+            .map(|list| ListUpdate::Changed {
+                name: list.name.clone(),
+                size: list.items.len(),
+                width: list.width,
+                items: list.items.iter().cloned().map(SlotUpdate::Set).collect(),
+            })
+            .collect();
+
+        Self { lists }
+    }
+}
+
+impl fmt::Display for InventoryUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for list in &self.lists {
+            match list {
+                ListUpdate::Keep(name) => writeln!(f, "KeepList {name}")?,
+                ListUpdate::Changed {
+                    name,
+                    size,
+                    width,
+                    items,
+                } => {
+                    writeln!(f, "List {name} {size}")?;
+                    writeln!(f, "Width {width}")?;
+                    for item in items {
+                        match item {
+                            SlotUpdate::Keep => writeln!(f, "Keep")?,
+                            SlotUpdate::Set(item) if item.is_empty() => writeln!(f, "Empty")?,
+                            SlotUpdate::Set(item) => writeln!(f, "Item {item}")?,
+                        }
+                    }
+                    writeln!(f, "EndInventoryList")?;
+                }
+            }
+        }
+
+        writeln!(f, "EndInventory")
+    }
+}
+
+impl Serialize for InventoryUpdate {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        Self::parse(&String::from_utf8(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlisted_slots_are_emptied() {
+        let mut inventory = Inventory::default();
+
+        let full = concat!(
+            "List main 3\n",
+            "Width 0\n",
+            "Item default:dirt 10\n",
+            "Item default:stone\n",
+            "Item default:wood 5\n",
+            "EndInventoryList\n",
+            "EndInventory\n",
+        );
+        inventory.apply(&InventoryUpdate::parse(full).unwrap());
+
+        let short = "List main 3\nWidth 0\nKeep\nEndInventoryList\nEndInventory\n";
+        inventory.apply(&InventoryUpdate::parse(short).unwrap());
+
+        let items = &inventory.list("main").unwrap().items;
+        assert_eq!(
+            items,
+            &[
+                ItemStack::new("default:dirt", 10),
+                ItemStack::default(),
+                ItemStack::default()
+            ]
+        );
+    }
+}
@@ -1,3 +1,9 @@
+mod error;
+pub mod formspec;
 pub mod game;
+pub mod inventory;
+pub mod object;
 pub mod spatial;
 pub mod world;
+
+pub use crate::error::{Error, Result};
@@ -0,0 +1,560 @@
+use crate::object::properties::ObjectProperties;
+use crate::object::ObjectInitData;
+use glam::{I16Vec2, Vec2, Vec3};
+use mtt_serialize::{deserialize_optional, Error, Result, Serialize};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+
+const AO_CMD_SET_PROPERTIES: u8 = 0;
+const AO_CMD_UPDATE_POSITION: u8 = 1;
+const AO_CMD_SET_TEXTURE_MOD: u8 = 2;
+const AO_CMD_SET_SPRITE: u8 = 3;
+const AO_CMD_PUNCHED: u8 = 4;
+const AO_CMD_UPDATE_ARMOR_GROUPS: u8 = 5;
+const AO_CMD_SET_ANIMATION: u8 = 6;
+const AO_CMD_SET_BONE_POSITION: u8 = 7;
+const AO_CMD_ATTACH_TO: u8 = 8;
+const AO_CMD_SET_PHYSICS_OVERRIDE: u8 = 9;
+const AO_CMD_SPAWN_INFANT: u8 = 11;
+const AO_CMD_SET_ANIMATION_SPEED: u8 = 12;
+
+/// Since this version (Minetest 5.8), physics overrides carry climbing,
+/// crouching, liquid and acceleration multipliers.
+const PROTOCOL_VERSION_PHYSICS_EXTENDED: u16 = 43;
+
+fn serialize_vec2<W: Write>(value: Vec2, w: &mut W) -> Result<()> {
+    value.x.serialize(w)?;
+    value.y.serialize(w)
+}
+
+fn deserialize_vec2<R: Read>(r: &mut R) -> Result<Vec2> {
+    Ok(Vec2::new(f32::deserialize(r)?, f32::deserialize(r)?))
+}
+
+/// Multipliers and switches applied to the movement of a player.
+#[derive(Debug, Clone)]
+pub struct PhysicsOverride {
+    pub speed: f32,
+    pub jump: f32,
+    pub gravity: f32,
+    pub sneak: bool,
+    pub sneak_glitch: bool,
+    pub new_move: bool,
+    // Fields below were added in 5.8
+    pub speed_climb: Option<f32>,
+    pub speed_crouch: Option<f32>,
+    pub liquid_fluidity: Option<f32>,
+    pub liquid_fluidity_smooth: Option<f32>,
+    pub liquid_sink: Option<f32>,
+    pub acceleration_default: Option<f32>,
+    pub acceleration_air: Option<f32>,
+}
+
+impl PhysicsOverride {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.speed.serialize(w)?;
+        self.jump.serialize(w)?;
+        self.gravity.serialize(w)?;
+        // Switches are sent inverted, so that they default to true
+        (!self.sneak).serialize(w)?;
+        (!self.sneak_glitch).serialize(w)?;
+        (!self.new_move).serialize(w)?;
+
+        if mtt_serialize::versions().protocol < PROTOCOL_VERSION_PHYSICS_EXTENDED {
+            return Ok(());
+        }
+
+        let extended = [
+            self.speed_climb,
+            self.speed_crouch,
+            self.liquid_fluidity,
+            self.liquid_fluidity_smooth,
+            self.liquid_sink,
+            self.acceleration_default,
+            self.acceleration_air,
+        ];
+        for value in extended.into_iter().map_while(|value| value) {
+            value.serialize(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize(r: &mut Cursor<Vec<u8>>) -> Result<Self> {
+        let mut physics_override = Self {
+            speed: f32::deserialize(r)?,
+            jump: f32::deserialize(r)?,
+            gravity: f32::deserialize(r)?,
+            sneak: !deserialize_optional::<bool, _>(r)?.unwrap_or(false),
+            sneak_glitch: !deserialize_optional::<bool, _>(r)?.unwrap_or(false),
+            new_move: !deserialize_optional::<bool, _>(r)?.unwrap_or(false),
+            speed_climb: None,
+            speed_crouch: None,
+            liquid_fluidity: None,
+            liquid_fluidity_smooth: None,
+            liquid_sink: None,
+            acceleration_default: None,
+            acceleration_air: None,
+        };
+
+        // Only sent by servers from 5.8 onwards
+        if mtt_serialize::versions().protocol >= PROTOCOL_VERSION_PHYSICS_EXTENDED {
+            physics_override.speed_climb = deserialize_optional(r)?;
+            physics_override.speed_crouch = deserialize_optional(r)?;
+            physics_override.liquid_fluidity = deserialize_optional(r)?;
+            physics_override.liquid_fluidity_smooth = deserialize_optional(r)?;
+            physics_override.liquid_sink = deserialize_optional(r)?;
+            physics_override.acceleration_default = deserialize_optional(r)?;
+            physics_override.acceleration_air = deserialize_optional(r)?;
+        }
+
+        Ok(physics_override)
+    }
+}
+
+/// Message sent to a single active object. Positions are in tenths of a
+/// node, angles in degrees.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum ObjectCommand {
+    SetProperties(ObjectProperties),
+    UpdatePosition {
+        position: Vec3,
+        velocity: Vec3,
+        acceleration: Vec3,
+        rotation: Vec3,
+        do_interpolate: bool,
+        is_end_position: bool,
+        update_interval: f32,
+    },
+    SetTextureMod(String),
+    SetSprite {
+        base: I16Vec2,
+        frame_count: u16,
+        frame_length: f32,
+        select_horizontal_by_yaw_pitch: bool,
+    },
+    Punched {
+        hp: u16,
+    },
+    UpdateArmorGroups(HashMap<String, i16>),
+    SetAnimation {
+        range: Vec2,
+        speed: f32,
+        blend: f32,
+        looped: bool,
+    },
+    SetBonePosition {
+        bone: String,
+        position: Vec3,
+        rotation: Vec3,
+    },
+    AttachTo {
+        /// Detached if zero.
+        parent_id: u16,
+        bone: String,
+        position: Vec3,
+        rotation: Vec3,
+        force_visible: bool,
+    },
+    SetPhysicsOverride(PhysicsOverride),
+    SpawnInfant {
+        child_id: u16,
+        init: ObjectInitData,
+    },
+    SetAnimationSpeed(f32),
+    /// Commands which aren't known, or were made obsolete.
+    Unknown {
+        command: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl Serialize for ObjectCommand {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        match self {
+            ObjectCommand::SetProperties(properties) => {
+                AO_CMD_SET_PROPERTIES.serialize(w)?;
+                properties.serialize(w)?;
+            }
+            ObjectCommand::UpdatePosition {
+                position,
+                velocity,
+                acceleration,
+                rotation,
+                do_interpolate,
+                is_end_position,
+                update_interval,
+            } => {
+                AO_CMD_UPDATE_POSITION.serialize(w)?;
+                position.serialize(w)?;
+                velocity.serialize(w)?;
+                acceleration.serialize(w)?;
+                rotation.serialize(w)?;
+                do_interpolate.serialize(w)?;
+                is_end_position.serialize(w)?;
+                update_interval.serialize(w)?;
+            }
+            ObjectCommand::SetTextureMod(modifier) => {
+                AO_CMD_SET_TEXTURE_MOD.serialize(w)?;
+                modifier.serialize(w)?;
+            }
+            ObjectCommand::SetSprite {
+                base,
+                frame_count,
+                frame_length,
+                select_horizontal_by_yaw_pitch,
+            } => {
+                AO_CMD_SET_SPRITE.serialize(w)?;
+                base.x.serialize(w)?;
+                base.y.serialize(w)?;
+                frame_count.serialize(w)?;
+                frame_length.serialize(w)?;
+                select_horizontal_by_yaw_pitch.serialize(w)?;
+            }
+            ObjectCommand::Punched { hp } => {
+                AO_CMD_PUNCHED.serialize(w)?;
+                hp.serialize(w)?;
+            }
+            ObjectCommand::UpdateArmorGroups(groups) => {
+                AO_CMD_UPDATE_ARMOR_GROUPS.serialize(w)?;
+
+                let count: u16 = groups.len().try_into().map_err(|_| Error::TooLong {
+                    what: "armor groups",
+                    len: groups.len(),
+                })?;
+                count.serialize(w)?;
+
+                for (name, rating) in groups {
+                    name.serialize(w)?;
+                    rating.serialize(w)?;
+                }
+            }
+            ObjectCommand::SetAnimation {
+                range,
+                speed,
+                blend,
+                looped,
+            } => {
+                AO_CMD_SET_ANIMATION.serialize(w)?;
+                serialize_vec2(*range, w)?;
+                speed.serialize(w)?;
+                blend.serialize(w)?;
+                // Sent inverted, so that it defaults to true
+                (!looped).serialize(w)?;
+            }
+            ObjectCommand::SetBonePosition {
+                bone,
+                position,
+                rotation,
+            } => {
+                AO_CMD_SET_BONE_POSITION.serialize(w)?;
+                bone.serialize(w)?;
+                position.serialize(w)?;
+                rotation.serialize(w)?;
+            }
+            ObjectCommand::AttachTo {
+                parent_id,
+                bone,
+                position,
+                rotation,
+                force_visible,
+            } => {
+                AO_CMD_ATTACH_TO.serialize(w)?;
+                parent_id.serialize(w)?;
+                bone.serialize(w)?;
+                position.serialize(w)?;
+                rotation.serialize(w)?;
+                force_visible.serialize(w)?;
+            }
+            ObjectCommand::SetPhysicsOverride(physics_override) => {
+                AO_CMD_SET_PHYSICS_OVERRIDE.serialize(w)?;
+                physics_override.serialize(w)?;
+            }
+            ObjectCommand::SpawnInfant { child_id, init } => {
+                AO_CMD_SPAWN_INFANT.serialize(w)?;
+                child_id.serialize(w)?;
+                init.serialize(w)?;
+            }
+            ObjectCommand::SetAnimationSpeed(speed) => {
+                AO_CMD_SET_ANIMATION_SPEED.serialize(w)?;
+                speed.serialize(w)?;
+            }
+            ObjectCommand::Unknown { command, data } => {
+                command.serialize(w)?;
+                w.write_all(data)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let command = u8::deserialize(r)?;
+
+        // Commands span the rest of the message, and some have optional
+        // trailing fields
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        let r = &mut Cursor::new(data);
+
+        Ok(match command {
+            AO_CMD_SET_PROPERTIES => ObjectCommand::SetProperties(ObjectProperties::deserialize(r)?),
+            AO_CMD_UPDATE_POSITION => ObjectCommand::UpdatePosition {
+                position: Vec3::deserialize(r)?,
+                velocity: Vec3::deserialize(r)?,
+                acceleration: Vec3::deserialize(r)?,
+                rotation: Vec3::deserialize(r)?,
+                do_interpolate: bool::deserialize(r)?,
+                is_end_position: bool::deserialize(r)?,
+                update_interval: f32::deserialize(r)?,
+            },
+            AO_CMD_SET_TEXTURE_MOD => ObjectCommand::SetTextureMod(String::deserialize(r)?),
+            AO_CMD_SET_SPRITE => ObjectCommand::SetSprite {
+                base: I16Vec2::new(i16::deserialize(r)?, i16::deserialize(r)?),
+                frame_count: u16::deserialize(r)?,
+                frame_length: f32::deserialize(r)?,
+                select_horizontal_by_yaw_pitch: bool::deserialize(r)?,
+            },
+            AO_CMD_PUNCHED => ObjectCommand::Punched {
+                hp: u16::deserialize(r)?,
+            },
+            AO_CMD_UPDATE_ARMOR_GROUPS => {
+                let count = u16::deserialize(r)?;
+                let mut groups = HashMap::new();
+                for _ in 0..count {
+                    let name = String::deserialize(r)?;
+                    let rating = i16::deserialize(r)?;
+                    groups.insert(name, rating);
+                }
+
+                ObjectCommand::UpdateArmorGroups(groups)
+            }
+            AO_CMD_SET_ANIMATION => ObjectCommand::SetAnimation {
+                range: deserialize_vec2(r)?,
+                speed: f32::deserialize(r)?,
+                blend: f32::deserialize(r)?,
+                looped: !deserialize_optional::<bool, _>(r)?.unwrap_or(false),
+            },
+            AO_CMD_SET_BONE_POSITION => ObjectCommand::SetBonePosition {
+                bone: String::deserialize(r)?,
+                position: Vec3::deserialize(r)?,
+                rotation: Vec3::deserialize(r)?,
+            },
+            AO_CMD_ATTACH_TO => ObjectCommand::AttachTo {
+                // Minetest writes the ID as signed, but reads it back into
+                // an unsigned object ID
+                parent_id: u16::deserialize(r)?,
+                bone: String::deserialize(r)?,
+                position: Vec3::deserialize(r)?,
+                rotation: Vec3::deserialize(r)?,
+                force_visible: deserialize_optional(r)?.unwrap_or(false),
+            },
+            AO_CMD_SET_PHYSICS_OVERRIDE => ObjectCommand::SetPhysicsOverride(PhysicsOverride::deserialize(r)?),
+            AO_CMD_SPAWN_INFANT => ObjectCommand::SpawnInfant {
+                child_id: u16::deserialize(r)?,
+                init: ObjectInitData::deserialize(r)?,
+            },
+            AO_CMD_SET_ANIMATION_SPEED => ObjectCommand::SetAnimationSpeed(f32::deserialize(r)?),
+            command => ObjectCommand::Unknown {
+                command,
+                data: r.get_ref().clone(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mtt_serialize::{with_versions, Versions};
+
+    fn decode(data: &[u8]) -> ObjectCommand {
+        ObjectCommand::deserialize(&mut Cursor::new(data)).unwrap()
+    }
+
+    fn encode(command: &ObjectCommand) -> Vec<u8> {
+        let mut data = Vec::new();
+        command.serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn attach_to_high_parent_id() {
+        let mut data = vec![AO_CMD_ATTACH_TO, 0x80, 0x01];
+        "Head".to_string().serialize(&mut data).unwrap();
+        Vec3::new(0.0, 5.0, 0.0).serialize(&mut data).unwrap();
+        Vec3::new(0.0, 90.0, 0.0).serialize(&mut data).unwrap();
+        data.push(1);
+
+        let command = decode(&data);
+        let ObjectCommand::AttachTo {
+            parent_id,
+            ref bone,
+            force_visible,
+            ..
+        } = command
+        else {
+            panic!("unexpected command {command:?}");
+        };
+        assert_eq!(parent_id, 0x8001);
+        assert_eq!(bone, "Head");
+        assert!(force_visible);
+
+        assert_eq!(encode(&command), data);
+    }
+
+    #[test]
+    fn update_position() {
+        let mut data = vec![AO_CMD_UPDATE_POSITION];
+        Vec3::new(10.0, 20.0, 30.0).serialize(&mut data).unwrap();
+        Vec3::new(1.0, 0.0, -1.0).serialize(&mut data).unwrap();
+        Vec3::new(0.0, -9.81, 0.0).serialize(&mut data).unwrap();
+        Vec3::new(0.0, 180.0, 0.0).serialize(&mut data).unwrap();
+        data.extend([1, 0]);
+        0.2f32.serialize(&mut data).unwrap();
+
+        let command = decode(&data);
+        let ObjectCommand::UpdatePosition {
+            position,
+            velocity,
+            acceleration,
+            rotation,
+            do_interpolate,
+            is_end_position,
+            update_interval,
+        } = command
+        else {
+            panic!("unexpected command {command:?}");
+        };
+        assert_eq!(position, Vec3::new(10.0, 20.0, 30.0));
+        assert_eq!(velocity, Vec3::new(1.0, 0.0, -1.0));
+        assert_eq!(acceleration, Vec3::new(0.0, -9.81, 0.0));
+        assert_eq!(rotation, Vec3::new(0.0, 180.0, 0.0));
+        assert!(do_interpolate);
+        assert!(!is_end_position);
+        assert_eq!(update_interval, 0.2);
+
+        assert_eq!(encode(&command), data);
+    }
+
+    fn physics_override(extended: &[f32]) -> Vec<u8> {
+        let mut data = vec![AO_CMD_SET_PHYSICS_OVERRIDE];
+        for value in [1.5f32, 2.0, 0.5] {
+            value.serialize(&mut data).unwrap();
+        }
+        // Sneaking allowed, sneak glitch and new move disabled
+        data.extend([0, 1, 1]);
+        for value in extended {
+            value.serialize(&mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn physics_override_before_5_8() {
+        let data = physics_override(&[]);
+
+        let command = decode(&data);
+        let ObjectCommand::SetPhysicsOverride(ref physics) = command else {
+            panic!("unexpected command {command:?}");
+        };
+        assert_eq!((physics.speed, physics.jump, physics.gravity), (1.5, 2.0, 0.5));
+        assert!(physics.sneak);
+        assert!(!physics.sneak_glitch);
+        assert!(!physics.new_move);
+        assert_eq!(physics.speed_climb, None);
+        assert_eq!(physics.acceleration_air, None);
+
+        assert_eq!(encode(&command), data);
+    }
+
+    #[test]
+    fn physics_override_from_5_8() {
+        let extended = [1.0, 0.5, 1.0, 1.0, 1.0, 1.0, 2.0];
+        let data = physics_override(&extended);
+
+        let command = decode(&data);
+        let ObjectCommand::SetPhysicsOverride(ref physics) = command else {
+            panic!("unexpected command {command:?}");
+        };
+        assert_eq!(physics.speed_climb, Some(1.0));
+        assert_eq!(physics.speed_crouch, Some(0.5));
+        assert_eq!(physics.acceleration_air, Some(2.0));
+
+        assert_eq!(encode(&command), data);
+
+        // Fields after the first missing one can't be sent
+        let ObjectCommand::SetPhysicsOverride(mut physics) = command else {
+            unreachable!();
+        };
+        physics.liquid_fluidity = None;
+        let command = ObjectCommand::SetPhysicsOverride(physics);
+        assert_eq!(encode(&command), physics_override(&extended[..2]));
+    }
+
+    #[test]
+    fn physics_override_for_older_protocols() {
+        let versions = Versions {
+            protocol: PROTOCOL_VERSION_PHYSICS_EXTENDED - 1,
+            ..Versions::LATEST
+        };
+        let data = physics_override(&[1.0, 0.5]);
+
+        with_versions(versions, || {
+            let command = decode(&data);
+            let ObjectCommand::SetPhysicsOverride(ref physics) = command else {
+                panic!("unexpected command {command:?}");
+            };
+            assert_eq!(physics.speed_climb, None);
+            assert_eq!(physics.speed_crouch, None);
+
+            assert_eq!(encode(&command), physics_override(&[]));
+        });
+    }
+
+    #[test]
+    fn set_animation_without_looped() {
+        let mut data = vec![AO_CMD_SET_ANIMATION];
+        for value in [0.0f32, 79.0, 30.0, 0.0] {
+            value.serialize(&mut data).unwrap();
+        }
+
+        let command = decode(&data);
+        let ObjectCommand::SetAnimation {
+            range,
+            speed,
+            blend,
+            looped,
+        } = command
+        else {
+            panic!("unexpected command {command:?}");
+        };
+        assert_eq!(range, Vec2::new(0.0, 79.0));
+        assert_eq!(speed, 30.0);
+        assert_eq!(blend, 0.0);
+        assert!(looped);
+
+        // The flag is always written back, inverted
+        data.push(0);
+        assert_eq!(encode(&command), data);
+    }
+
+    #[test]
+    fn unknown_command() {
+        let data = [10, 1, 2, 3];
+
+        let command = decode(&data);
+        let ObjectCommand::Unknown {
+            command: code,
+            data: ref payload,
+        } = command
+        else {
+            panic!("unexpected command {command:?}");
+        };
+        assert_eq!(code, 10);
+        assert_eq!(payload, &[1, 2, 3]);
+
+        assert_eq!(encode(&command), data);
+    }
+}
@@ -0,0 +1,169 @@
+pub mod command;
+pub mod properties;
+
+use crate::object::command::ObjectCommand;
+use crate::object::properties::ObjectProperties;
+use glam::Vec3;
+use mtt_serialize::{Error, RawBytes32, Result, Serialize};
+use std::io::{Cursor, Read, Write};
+
+/// Type of every object sent by current servers.
+pub const ACTIVE_OBJECT_TYPE_GENERIC: u8 = 101;
+
+/// Data a generic active object, i.e. an entity or a player, is created
+/// from.
+#[derive(Debug, Clone)]
+pub struct ObjectInit {
+    pub name: String,
+    pub is_player: bool,
+    pub id: u16,
+    /// In tenths of a node.
+    pub position: Vec3,
+    /// Euler angles in degrees.
+    pub rotation: Vec3,
+    pub hp: u16,
+    /// Applied right after creating the object.
+    pub commands: Vec<ObjectCommand>,
+}
+
+impl Serialize for ObjectInit {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        1u8.serialize(w)?;
+        self.name.serialize(w)?;
+        self.is_player.serialize(w)?;
+        self.id.serialize(w)?;
+        self.position.serialize(w)?;
+        self.rotation.serialize(w)?;
+        self.hp.serialize(w)?;
+
+        let count: u8 = self.commands.len().try_into().map_err(|_| Error::TooLong {
+            what: "object commands",
+            len: self.commands.len(),
+        })?;
+        count.serialize(w)?;
+
+        for command in &self.commands {
+            let mut data = Vec::new();
+            command.serialize(&mut data)?;
+            RawBytes32(data).serialize(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let version = u8::deserialize(r)?;
+        if version < 1 {
+            return Err(Error::UnsupportedVersion {
+                what: "object init data",
+                version: version as u64,
+            });
+        }
+
+        let name = String::deserialize(r)?;
+        let is_player = bool::deserialize(r)?;
+        let id = u16::deserialize(r)?;
+        let position = Vec3::deserialize(r)?;
+        let rotation = Vec3::deserialize(r)?;
+        let hp = u16::deserialize(r)?;
+
+        let count = u8::deserialize(r)?;
+        let mut commands = Vec::new();
+        for _ in 0..count {
+            let data = RawBytes32::deserialize(r)?.0;
+            commands.push(ObjectCommand::deserialize(&mut Cursor::new(data))?);
+        }
+
+        Ok(Self {
+            name,
+            is_player,
+            id,
+            position,
+            rotation,
+            hp,
+            commands,
+        })
+    }
+}
+
+/// Type of an object followed by its init data.
+#[derive(Debug, Clone)]
+pub enum ObjectInitData {
+    Generic(ObjectInit),
+    /// Legacy object types aren't interpreted.
+    Other {
+        ty: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl Serialize for ObjectInitData {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        match self {
+            ObjectInitData::Generic(init) => {
+                let mut data = Vec::new();
+                init.serialize(&mut data)?;
+
+                ACTIVE_OBJECT_TYPE_GENERIC.serialize(w)?;
+                RawBytes32(data).serialize(w)
+            }
+            ObjectInitData::Other { ty, data } => {
+                ty.serialize(w)?;
+                RawBytes32(data.clone()).serialize(w)
+            }
+        }
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let ty = u8::deserialize(r)?;
+        let data = RawBytes32::deserialize(r)?.0;
+
+        Ok(match ty {
+            ACTIVE_OBJECT_TYPE_GENERIC => ObjectInitData::Generic(ObjectInit::deserialize(&mut Cursor::new(data))?),
+            ty => ObjectInitData::Other { ty, data },
+        })
+    }
+}
+
+/// Entity or player currently known to the client.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub name: String,
+    pub is_player: bool,
+    pub position: Vec3,
+    pub rotation: Vec3,
+    pub hp: u16,
+    pub properties: ObjectProperties,
+}
+
+impl Object {
+    pub fn new(init: &ObjectInit) -> Self {
+        let mut object = Self {
+            name: init.name.clone(),
+            is_player: init.is_player,
+            // Positions are sent in tenths of a node
+            position: init.position / 10.0,
+            rotation: init.rotation,
+            hp: init.hp,
+            properties: ObjectProperties::default(),
+        };
+
+        for command in &init.commands {
+            object.apply(command);
+        }
+
+        object
+    }
+
+    pub fn apply(&mut self, command: &ObjectCommand) {
+        match command {
+            ObjectCommand::UpdatePosition { position, rotation, .. } => {
+                self.position = *position / 10.0;
+                self.rotation = *rotation;
+            }
+            ObjectCommand::Punched { hp } => self.hp = *hp,
+            ObjectCommand::SetProperties(properties) => self.properties = properties.clone(),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use crate::game::node::Argb;
+use crate::spatial::Aabb;
+use glam::{I16Vec2, Vec3};
+use mtt_serialize::{deserialize_optional, Error, Result, Serialize};
+use std::io::{Cursor, Read, Write};
+
+const VERSION: u8 = 4;
+
+/// Sent instead of a nametag background color to request the default one.
+const NULL_BGCOLOR: Argb = Argb { a: 0, r: 1, g: 1, b: 1 };
+
+const WHITE: Argb = Argb {
+    a: 255,
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Visual {
+    Sprite,
+    UprightSprite,
+    Cube,
+    Mesh,
+    Item,
+    WieldItem,
+    Other(String),
+}
+
+impl Visual {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "sprite" => Visual::Sprite,
+            "upright_sprite" => Visual::UprightSprite,
+            "cube" => Visual::Cube,
+            "mesh" => Visual::Mesh,
+            "item" => Visual::Item,
+            "wielditem" => Visual::WieldItem,
+            name => Visual::Other(name.to_string()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Visual::Sprite => "sprite",
+            Visual::UprightSprite => "upright_sprite",
+            Visual::Cube => "cube",
+            Visual::Mesh => "mesh",
+            Visual::Item => "item",
+            Visual::WieldItem => "wielditem",
+            Visual::Other(name) => name,
+        }
+    }
+}
+
+/// Appearance and physical behaviour of an active object. Boxes and heights
+/// are in nodes, except for `step_height`.
+#[derive(Debug, Clone)]
+pub struct ObjectProperties {
+    pub hp_max: u16,
+    pub physical: bool,
+    pub collision_box: Aabb,
+    pub selection_box: Aabb,
+    pub pointable: bool,
+    pub visual: Visual,
+    pub visual_size: Vec3,
+    pub textures: Vec<String>,
+    pub sprite_div: I16Vec2,
+    pub initial_sprite_base_position: I16Vec2,
+    pub is_visible: bool,
+    pub makes_footstep_sound: bool,
+    /// In radians per second.
+    pub automatic_rotate: f32,
+    pub mesh: String,
+    pub colors: Vec<Argb>,
+    pub collide_with_objects: bool,
+    /// In tenths of a node, like positions.
+    pub step_height: f32,
+    pub automatic_face_movement_dir: bool,
+    /// In degrees.
+    pub automatic_face_movement_dir_offset: f32,
+    pub backface_culling: bool,
+    pub nametag: String,
+    pub nametag_color: Argb,
+    /// Negative if there's no limit.
+    pub automatic_face_movement_max_rotation_per_sec: f32,
+    pub infotext: String,
+    pub wield_item: String,
+    pub glow: i8,
+    pub breath_max: u16,
+    pub eye_height: f32,
+    /// In degrees, zero if zooming isn't allowed.
+    pub zoom_fov: f32,
+    pub use_texture_alpha: bool,
+    // Fields below are missing when talking to older servers, and keep
+    // their defaults
+    pub damage_texture_modifier: String,
+    pub shaded: bool,
+    pub show_on_minimap: bool,
+    /// Default background is used if not set.
+    pub nametag_bgcolor: Option<Argb>,
+    pub rotate_selection_box: bool,
+}
+
+impl Default for ObjectProperties {
+    fn default() -> Self {
+        let unit_box = Aabb {
+            min: Vec3::splat(-0.5),
+            max: Vec3::splat(0.5),
+        };
+
+        Self {
+            hp_max: 1,
+            physical: false,
+            collision_box: unit_box.clone(),
+            selection_box: unit_box,
+            pointable: true,
+            visual: Visual::Sprite,
+            visual_size: Vec3::ONE,
+            textures: vec!["unknown_object.png".to_string()],
+            sprite_div: I16Vec2::ONE,
+            initial_sprite_base_position: I16Vec2::ZERO,
+            is_visible: true,
+            makes_footstep_sound: false,
+            automatic_rotate: 0.0,
+            mesh: String::new(),
+            colors: vec![WHITE],
+            collide_with_objects: true,
+            step_height: 0.0,
+            automatic_face_movement_dir: false,
+            automatic_face_movement_dir_offset: 0.0,
+            backface_culling: true,
+            nametag: String::new(),
+            nametag_color: WHITE,
+            automatic_face_movement_max_rotation_per_sec: -1.0,
+            infotext: String::new(),
+            wield_item: String::new(),
+            glow: 0,
+            breath_max: 0,
+            eye_height: 1.625,
+            zoom_fov: 0.0,
+            use_texture_alpha: false,
+            damage_texture_modifier: "^[brighten".to_string(),
+            shaded: true,
+            show_on_minimap: false,
+            nametag_bgcolor: None,
+            rotate_selection_box: false,
+        }
+    }
+}
+
+fn serialize_i16vec2<W: Write>(value: I16Vec2, w: &mut W) -> Result<()> {
+    value.x.serialize(w)?;
+    value.y.serialize(w)
+}
+
+fn deserialize_i16vec2<R: Read>(r: &mut R) -> Result<I16Vec2> {
+    Ok(I16Vec2::new(i16::deserialize(r)?, i16::deserialize(r)?))
+}
+
+impl Serialize for ObjectProperties {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        VERSION.serialize(w)?;
+        self.hp_max.serialize(w)?;
+        self.physical.serialize(w)?;
+        // Weight, which isn't used anymore
+        0f32.serialize(w)?;
+        self.collision_box.serialize(w)?;
+        self.selection_box.serialize(w)?;
+        self.pointable.serialize(w)?;
+        self.visual.name().to_string().serialize(w)?;
+        self.visual_size.serialize(w)?;
+        self.textures.serialize(w)?;
+        serialize_i16vec2(self.sprite_div, w)?;
+        serialize_i16vec2(self.initial_sprite_base_position, w)?;
+        self.is_visible.serialize(w)?;
+        self.makes_footstep_sound.serialize(w)?;
+        self.automatic_rotate.serialize(w)?;
+        self.mesh.serialize(w)?;
+        self.colors.serialize(w)?;
+        self.collide_with_objects.serialize(w)?;
+        self.step_height.serialize(w)?;
+        self.automatic_face_movement_dir.serialize(w)?;
+        self.automatic_face_movement_dir_offset.serialize(w)?;
+        self.backface_culling.serialize(w)?;
+        self.nametag.serialize(w)?;
+        self.nametag_color.serialize(w)?;
+        self.automatic_face_movement_max_rotation_per_sec.serialize(w)?;
+        self.infotext.serialize(w)?;
+        self.wield_item.serialize(w)?;
+        self.glow.serialize(w)?;
+        self.breath_max.serialize(w)?;
+        self.eye_height.serialize(w)?;
+        self.zoom_fov.serialize(w)?;
+        self.use_texture_alpha.serialize(w)?;
+        self.damage_texture_modifier.serialize(w)?;
+        self.shaded.serialize(w)?;
+        self.show_on_minimap.serialize(w)?;
+        self.nametag_bgcolor.as_ref().unwrap_or(&NULL_BGCOLOR).serialize(w)?;
+        self.rotate_selection_box.serialize(w)
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let version = u8::deserialize(r)?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion {
+                what: "object properties",
+                version: version as u64,
+            });
+        }
+
+        // Fields were appended over time, so the ones at the end may be
+        // missing
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        let r = &mut Cursor::new(data);
+
+        let hp_max = u16::deserialize(r)?;
+        let physical = bool::deserialize(r)?;
+        let _weight = f32::deserialize(r)?;
+
+        let mut properties = Self {
+            hp_max,
+            physical,
+            collision_box: Aabb::deserialize(r)?,
+            selection_box: Aabb::deserialize(r)?,
+            pointable: bool::deserialize(r)?,
+            visual: Visual::from_name(&String::deserialize(r)?),
+            visual_size: Vec3::deserialize(r)?,
+            textures: Vec::deserialize(r)?,
+            sprite_div: deserialize_i16vec2(r)?,
+            initial_sprite_base_position: deserialize_i16vec2(r)?,
+            is_visible: bool::deserialize(r)?,
+            makes_footstep_sound: bool::deserialize(r)?,
+            automatic_rotate: f32::deserialize(r)?,
+            mesh: String::deserialize(r)?,
+            colors: Vec::deserialize(r)?,
+            collide_with_objects: bool::deserialize(r)?,
+            step_height: f32::deserialize(r)?,
+            automatic_face_movement_dir: bool::deserialize(r)?,
+            automatic_face_movement_dir_offset: f32::deserialize(r)?,
+            backface_culling: bool::deserialize(r)?,
+            nametag: String::deserialize(r)?,
+            nametag_color: Argb::deserialize(r)?,
+            automatic_face_movement_max_rotation_per_sec: f32::deserialize(r)?,
+            infotext: String::deserialize(r)?,
+            wield_item: String::deserialize(r)?,
+            glow: i8::deserialize(r)?,
+            breath_max: u16::deserialize(r)?,
+            eye_height: f32::deserialize(r)?,
+            zoom_fov: f32::deserialize(r)?,
+            use_texture_alpha: bool::deserialize(r)?,
+            ..Self::default()
+        };
+
+        if let Some(modifier) = deserialize_optional(r)? {
+            properties.damage_texture_modifier = modifier;
+        }
+        if let Some(shaded) = deserialize_optional(r)? {
+            properties.shaded = shaded;
+        }
+        if let Some(show_on_minimap) = deserialize_optional(r)? {
+            properties.show_on_minimap = show_on_minimap;
+        }
+        if let Some(color) = deserialize_optional::<Argb, _>(r)? {
+            properties.nametag_bgcolor = (color != NULL_BGCOLOR).then_some(color);
+        }
+        if let Some(rotate_selection_box) = deserialize_optional(r)? {
+            properties.rotate_selection_box = rotate_selection_box;
+        }
+
+        Ok(properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Properties of a player with the defaults of Minetest 5.7, laid out
+    /// like `ObjectProperties::serialize` writes them.
+    #[rustfmt::skip]
+    const PLAYER: &[u8] = &[
+        // Version
+        0x04,
+        // HP max
+        0x00, 0x14,
+        // Physical
+        0x00,
+        // Weight
+        0x00, 0x00, 0x00, 0x00,
+        // Collision box
+        0xBE, 0x99, 0x99, 0x9A, 0x00, 0x00, 0x00, 0x00, 0xBE, 0x99, 0x99, 0x9A,
+        0x3E, 0x99, 0x99, 0x9A, 0x3F, 0xE2, 0x8F, 0x5C, 0x3E, 0x99, 0x99, 0x9A,
+        // Selection box
+        0xBE, 0x99, 0x99, 0x9A, 0x00, 0x00, 0x00, 0x00, 0xBE, 0x99, 0x99, 0x9A,
+        0x3E, 0x99, 0x99, 0x9A, 0x3F, 0xE2, 0x8F, 0x5C, 0x3E, 0x99, 0x99, 0x9A,
+        // Pointable
+        0x01,
+        // Visual
+        0x00, 0x0E, 0x75, 0x70, 0x72, 0x69, 0x67, 0x68, 0x74, 0x5F, 0x73, 0x70,
+        0x72, 0x69, 0x74, 0x65,
+        // Visual size
+        0x3F, 0x80, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x3F, 0x80, 0x00, 0x00,
+        // Textures
+        0x00, 0x02, 0x00, 0x0A, 0x70, 0x6C, 0x61, 0x79, 0x65, 0x72, 0x2E, 0x70,
+        0x6E, 0x67, 0x00, 0x0F, 0x70, 0x6C, 0x61, 0x79, 0x65, 0x72, 0x5F, 0x62,
+        0x61, 0x63, 0x6B, 0x2E, 0x70, 0x6E, 0x67,
+        // Sprite div, initial sprite base position
+        0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        // Is visible, makes footstep sound
+        0x01, 0x01,
+        // Automatic rotate
+        0x00, 0x00, 0x00, 0x00,
+        // Mesh
+        0x00, 0x0D, 0x63, 0x68, 0x61, 0x72, 0x61, 0x63, 0x74, 0x65, 0x72, 0x2E,
+        0x62, 0x33, 0x64,
+        // Colors
+        0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF,
+        // Collide with objects
+        0x01,
+        // Step height
+        0x40, 0xC0, 0x00, 0x00,
+        // Automatic face movement dir and offset
+        0x00, 0x00, 0x00, 0x00, 0x00,
+        // Backface culling
+        0x01,
+        // Nametag and color
+        0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+        // Automatic face movement max rotation per second
+        0xBF, 0x80, 0x00, 0x00,
+        // Infotext, wield item
+        0x00, 0x00, 0x00, 0x00,
+        // Glow
+        0x00,
+        // Breath max
+        0x00, 0x0A,
+        // Eye height
+        0x3F, 0xD0, 0x00, 0x00,
+        // Zoom fov
+        0x00, 0x00, 0x00, 0x00,
+        // Use texture alpha
+        0x00,
+        // Damage texture modifier
+        0x00, 0x0A, 0x5E, 0x5B, 0x62, 0x72, 0x69, 0x67, 0x68, 0x74, 0x65, 0x6E,
+        // Shaded, show on minimap
+        0x01, 0x01,
+        // Nametag background color
+        0x00, 0x01, 0x01, 0x01,
+        // Rotate selection box
+        0x00,
+    ];
+
+    #[test]
+    fn player_properties_survive_a_round_trip() {
+        let properties = ObjectProperties::deserialize(&mut Cursor::new(PLAYER)).unwrap();
+
+        assert_eq!(properties.hp_max, 20);
+        assert_eq!(properties.collision_box.min, Vec3::new(-0.3, 0.0, -0.3));
+        assert_eq!(properties.selection_box.max, Vec3::new(0.3, 1.77, 0.3));
+        assert_eq!(properties.visual, Visual::UprightSprite);
+        assert_eq!(properties.visual_size, Vec3::new(1.0, 2.0, 1.0));
+        assert_eq!(properties.textures, ["player.png", "player_back.png"]);
+        assert_eq!(properties.mesh, "character.b3d");
+        assert_eq!(properties.step_height, 6.0);
+        assert_eq!(properties.eye_height, 1.625);
+        assert_eq!(properties.breath_max, 10);
+        assert!(properties.show_on_minimap);
+        assert_eq!(properties.nametag_bgcolor, None);
+
+        let mut data = Vec::new();
+        properties.serialize(&mut data).unwrap();
+        assert_eq!(data, PLAYER);
+    }
+}
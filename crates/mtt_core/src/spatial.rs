@@ -3,6 +3,6 @@ use mtt_macros::Serialize;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Aabb {
-    min: Vec3,
-    max: Vec3,
+    pub min: Vec3,
+    pub max: Vec3,
 }
@@ -1,5 +1,6 @@
 use crate::world::node::Node;
-use mtt_serialize::Serialize;
+use flate2::read::ZlibDecoder;
+use mtt_serialize::{Error, Result, Serialize};
 use std::io::{Cursor, Read, Write};
 
 #[derive(Debug, Clone)]
@@ -26,40 +27,132 @@ impl Block {
     }
 }
 
+/// Oldest serialization version whose block format is understood.
+const SERIALIZATION_VERSION_MIN: u8 = 28;
+
+/// Since this version, blocks are compressed with zstd as a whole, instead
+/// of node data and metadata being compressed with zlib separately.
+const SERIALIZATION_VERSION_ZSTD: u8 = 29;
+
+fn deserialize_header<R: Read>(r: &mut R) -> Result<()> {
+    let _flags = u8::deserialize(r)?;
+    let _lighting_complete = u16::deserialize(r)?;
+
+    let content_width = u8::deserialize(r)?;
+    let params_width = u8::deserialize(r)?;
+    if content_width != 2 {
+        return Err(Error::Unexpected {
+            what: "content width",
+            expected: 2,
+            actual: content_width as u64,
+        });
+    }
+    if params_width != 2 {
+        return Err(Error::Unexpected {
+            what: "params width",
+            expected: 2,
+            actual: params_width as u64,
+        });
+    }
+
+    Ok(())
+}
+
 impl Serialize for Block {
-    fn serialize<W: Write>(&self, _w: &mut W) -> anyhow::Result<()> {
-        todo!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        let version = mtt_serialize::versions().serialization;
+        if version < SERIALIZATION_VERSION_ZSTD {
+            return Err(Error::UnsupportedVersion {
+                what: "block serialization",
+                version: version as u64,
+            });
+        }
+
+        let mut data = Vec::with_capacity(6 + self.node_data.len());
+        // No flags, lighting is complete in every direction
+        0u8.serialize(&mut data)?;
+        0xFFFFu16.serialize(&mut data)?;
+        2u8.serialize(&mut data)?;
+        2u8.serialize(&mut data)?;
+        data.extend_from_slice(&self.node_data);
+        // Node metadata isn't kept, so there's never any to send
+        0u8.serialize(&mut data)?;
+
+        let data = zstd::encode_all(data.as_slice(), 0)?;
+        w.write_all(&data)?;
+
+        // Network-specific data version, see `deserialize`
+        2u8.serialize(w)
     }
 
-    fn deserialize<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let version = mtt_serialize::versions().serialization;
+        if version < SERIALIZATION_VERSION_MIN {
+            return Err(Error::UnsupportedVersion {
+                what: "block serialization",
+                version: version as u64,
+            });
+        }
+
         // FIXME: take length directly from reader
         let mut data = Vec::new();
         r.read_to_end(&mut data)?;
         let len = data.len() as u64;
 
+        if version < SERIALIZATION_VERSION_ZSTD {
+            let r = &mut Cursor::new(data.as_slice());
+            deserialize_header(r)?;
+
+            // Node data is followed by separately compressed metadata, which
+            // is of no interest
+            let node_data = &data[r.position() as usize..];
+            let mut node_data = mtt_serialize::decompress(ZlibDecoder::new(node_data))?;
+            if node_data.len() < Block::VOLUME * 4 {
+                return Err(Error::Truncated);
+            }
+            node_data.truncate(Block::VOLUME * 4);
+
+            return Ok(Self { node_data });
+        }
+
         // Due to the legendary amount of legacy, server sends an additional
         // uncompressed byte at the end. This byte confuses zstd reader into
         // reading an additional frame, and the reader fails to recognize that
         // there just isn't enough data to decompress anything.
         //
         // Since the byte isn't very important, cutting it off seems reasonable.
-        let mut decoder = zstd::Decoder::new(Cursor::new(data).take(len - 1))?;
-        let mut data = Vec::new();
-        decoder.read_to_end(&mut data)?;
+        let decoder =
+            zstd::Decoder::new(Cursor::new(data).take(len.saturating_sub(1))).map_err(Error::Decompression)?;
+        let data = mtt_serialize::decompress(decoder)?;
 
         let r = &mut Cursor::new(data);
+        deserialize_header(r)?;
 
-        let _flags = u8::deserialize(r)?;
-        let _lighting_complete = u16::deserialize(r)?;
+        let node_data = mtt_serialize::read_bytes(r, Block::VOLUME * 4)?;
 
-        let content_width = u8::deserialize(r)?;
-        let params_width = u8::deserialize(r)?;
-        anyhow::ensure!(content_width == 2, "invalid content width");
-        anyhow::ensure!(params_width == 2, "invalid params width");
+        Ok(Self { node_data })
+    }
+}
 
-        let mut node_data = vec![0; Block::VOLUME * 4];
-        r.read_exact(&mut node_data)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mtt_serialize::{with_versions, Versions};
 
-        Ok(Self { node_data })
+    #[test]
+    fn blocks_survive_a_round_trip() {
+        let node_data: Vec<u8> = (0..Block::VOLUME * 4).map(|i| i as u8).collect();
+        let block = Block {
+            node_data: node_data.clone(),
+        };
+
+        with_versions(Versions::LATEST, || {
+            let mut data = Vec::new();
+            block.serialize(&mut data).unwrap();
+            assert_eq!(data.last(), Some(&2));
+
+            let block = Block::deserialize(&mut data.as_slice()).unwrap();
+            assert_eq!(block.node_data, node_data);
+        });
     }
 }
@@ -2,7 +2,10 @@ pub mod block;
 pub mod map;
 pub mod node;
 
+use crate::inventory::Inventory;
+use crate::object::Object;
 use glam::Vec3;
+use std::collections::HashMap;
 
 pub use self::block::Block;
 pub use crate::world::map::Map;
@@ -10,6 +13,9 @@ pub use crate::world::map::Map;
 pub struct Player {
     pub position: Vec3,
     pub look_dir: Vec3,
+    pub inventory: Inventory,
+    /// Formspec shown when opening the inventory.
+    pub inventory_formspec: String,
 }
 
 impl Player {
@@ -17,6 +23,8 @@ impl Player {
         Self {
             position: Vec3::new(-10.0, 10.0, -10.0),
             look_dir: Vec3::new(1.0, -1.0, 1.0).normalize(),
+            inventory: Inventory::default(),
+            inventory_formspec: String::new(),
         }
     }
 }
@@ -26,6 +34,9 @@ pub struct WorldState {
     pub time: f32,
     pub time_speed: f32,
     pub map: Map,
+    pub objects: HashMap<u16, Object>,
+    /// Inventories which don't belong to a player or a node, by name.
+    pub detached_inventories: HashMap<String, Inventory>,
 }
 
 impl WorldState {
@@ -35,6 +46,18 @@ impl WorldState {
             time: 0.0,
             time_speed: 0.0,
             map: Map::new(),
+            objects: HashMap::new(),
+            detached_inventories: HashMap::new(),
+        }
+    }
+
+    /// Looks up an inventory by the location formspecs refer to it with.
+    /// Only the player's own and detached inventories are known.
+    pub fn inventory(&self, location: &str) -> Option<&Inventory> {
+        match location.split_once(':') {
+            None if location == "current_player" => Some(&self.player.inventory),
+            Some(("detached", name)) => self.detached_inventories.get(name),
+            _ => None,
         }
     }
 }
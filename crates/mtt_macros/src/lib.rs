@@ -36,7 +36,9 @@ fn make_packet_impls(input: &ItemEnum) -> TokenStream {
         let ty = variant.fields.iter().next().unwrap();
 
         quote! {
-             #id => Ok(#ident::#v_ident(#ty::deserialize(r)?)),
+             #id => #ty::deserialize(r)
+                .map(#ident::#v_ident)
+                .map_err(|error| mtt_serialize::Error::packet(stringify!(#v_ident), error)),
         }
     });
 
@@ -55,18 +57,18 @@ fn make_packet_impls(input: &ItemEnum) -> TokenStream {
 
     quote! {
         impl mtt_serialize::Serialize for #ident {
-            fn serialize<W: std::io::Write>(&self, w: &mut W) -> anyhow::Result<()> {
+            fn serialize<W: std::io::Write>(&self, w: &mut W) -> mtt_serialize::Result<()> {
                 match self {
                     #(#serialize_variants),*
                 }
                 Ok(())
             }
 
-            fn deserialize<R: std::io::Read>(r: &mut R) -> anyhow::Result<Self> {
+            fn deserialize<R: std::io::Read>(r: &mut R) -> mtt_serialize::Result<Self> {
                 let id = u16::deserialize(r)?;
                 match id {
                     #(#deserialize_variants)*
-                    _ => anyhow::bail!("unknown packet id: {}", id),
+                    _ => Err(mtt_serialize::Error::UnknownPacketId(id)),
                 }
             }
         }
@@ -121,7 +123,9 @@ pub fn derive_serialize(input: proc_macro::TokenStream) -> proc_macro::TokenStre
         let ident = &field.ident;
 
         quote! {
-            self.#ident.serialize(w)?;
+            self.#ident
+                .serialize(w)
+                .map_err(|error| mtt_serialize::Error::field(stringify!(#ident), error))?;
         }
     });
 
@@ -130,18 +134,19 @@ pub fn derive_serialize(input: proc_macro::TokenStream) -> proc_macro::TokenStre
         let ty = &field.ty;
 
         quote! {
-            #ident: #ty::deserialize(r)?,
+            #ident: <#ty>::deserialize(r)
+                .map_err(|error| mtt_serialize::Error::field(stringify!(#ident), error))?,
         }
     });
 
     let tokens = quote! {
         impl #impl_generics mtt_serialize::Serialize for #ident #ty_generics #where_clause {
-            fn serialize<W: std::io::Write>(&self, w: &mut W) -> anyhow::Result<()> {
+            fn serialize<W: std::io::Write>(&self, w: &mut W) -> mtt_serialize::Result<()> {
                 #(#serialize_fields)*
                 Ok(())
             }
 
-            fn deserialize<R: std::io::Read>(r: &mut R) -> anyhow::Result<Self> {
+            fn deserialize<R: std::io::Read>(r: &mut R) -> mtt_serialize::Result<Self> {
                 Ok(#ident {
                     #(#deserialize_fields)*
                 })
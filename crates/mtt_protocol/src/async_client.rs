@@ -0,0 +1,205 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::clientbound::ClientBound;
+use crate::serverbound::ServerBound;
+use crate::{Client, ClientConfig, ConnectionState, DisconnectReason, Input, Output, Result};
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+#[derive(Debug, thiserror::Error)]
+#[error("connection is closed")]
+pub struct ConnectionClosed;
+
+/// Client connection driven by the tokio runtime.
+///
+/// Received packets are available as a [`Stream`], and packets are sent
+/// reliably through the [`Sink`] implementation. The stream ends once the
+/// connection is closed.
+pub struct AsyncClient {
+    packets: mpsc::UnboundedReceiver<ClientBound>,
+    sender: Option<mpsc::UnboundedSender<ServerBound>>,
+    state: watch::Receiver<ConnectionState>,
+    driver: JoinHandle<Result<DisconnectReason>>,
+}
+
+impl AsyncClient {
+    /// Binds a socket, starts the login sequence and spawns a task which
+    /// drives the connection.
+    pub async fn connect<A: ToSocketAddrs>(address: A, config: ClientConfig) -> io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(address).await?;
+
+        let (packets_tx, packets) = mpsc::unbounded_channel();
+        let (sender, serverbound_rx) = mpsc::unbounded_channel();
+        let (state_tx, state) = watch::channel(ConnectionState::Start);
+
+        let driver = Driver {
+            client: Client::new(config),
+            socket,
+            serverbound_rx,
+            packets_tx,
+            state_tx,
+        };
+
+        Ok(Self {
+            packets,
+            sender: Some(sender),
+            state,
+            driver: tokio::spawn(driver.run()),
+        })
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Disconnects from the server and returns the reason the connection
+    /// was closed, which may have happened earlier.
+    pub async fn disconnect(self) -> Result<DisconnectReason> {
+        drop(self.sender);
+        self.driver.await.map_err(io::Error::from)?
+    }
+}
+
+impl Stream for AsyncClient {
+    type Item = ClientBound;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.packets.poll_recv(cx)
+    }
+}
+
+impl Sink<ServerBound> for AsyncClient {
+    type Error = ConnectionClosed;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &self.sender {
+            Some(sender) if !sender.is_closed() => Poll::Ready(Ok(())),
+            _ => Poll::Ready(Err(ConnectionClosed)),
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, packet: ServerBound) -> Result<(), Self::Error> {
+        match &self.sender {
+            Some(sender) => sender.send(packet).map_err(|_| ConnectionClosed),
+            None => Err(ConnectionClosed),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Closing the sink disconnects from the server.
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sender = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Errors caused by ICMP messages about earlier datagrams, which don't mean
+/// the socket is broken.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset | io::ErrorKind::Interrupted
+    )
+}
+
+/// Sleeps until `deadline`, or forever if there is none.
+async fn sleep_until(deadline: Option<std::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+struct Driver {
+    client: Client,
+    socket: UdpSocket,
+    serverbound_rx: mpsc::UnboundedReceiver<ServerBound>,
+    packets_tx: mpsc::UnboundedSender<ClientBound>,
+    state_tx: watch::Sender<ConnectionState>,
+}
+
+impl Driver {
+    async fn run(mut self) -> Result<DisconnectReason> {
+        let mut buf = vec![0; u16::MAX as usize];
+
+        self.client.handle_input(Input::Connect)?;
+
+        loop {
+            if let Some(reason) = self.flush().await? {
+                return Ok(reason);
+            }
+
+            let deadline = self.client.next_deadline();
+
+            tokio::select! {
+                result = self.socket.recv(&mut buf) => {
+                    let len = match result {
+                        Ok(len) => len,
+                        // Nothing may be listening yet, or the server is
+                        // restarting. If it doesn't come back, the
+                        // connection times out
+                        Err(error) if is_transient(&error) => continue,
+                        Err(error) => return Err(error.into()),
+                    };
+
+                    self.client.handle_input(Input::Tick(Instant::now().into_std()))?;
+
+                    // Malformed datagrams are dropped instead of tearing down the connection
+                    let _ = self.client.handle_input(Input::Receive(&buf[..len]));
+                }
+                packet = self.serverbound_rx.recv() => {
+                    let input = match packet {
+                        Some(packet) => Input::Packet { is_reliable: true, packet },
+                        None => Input::Disconnect,
+                    };
+
+                    // Frames are timestamped with the client's clock
+                    self.client.handle_input(Input::Tick(Instant::now().into_std()))?;
+                    self.client.handle_input(input)?;
+                }
+                _ = sleep_until(deadline) => {
+                    self.client.handle_input(Input::Tick(Instant::now().into_std()))?;
+                }
+            }
+        }
+    }
+
+    /// Performs I/O requested by the client. Returns disconnect reason once
+    /// the connection is closed.
+    async fn flush(&mut self) -> Result<Option<DisconnectReason>> {
+        let outputs: Vec<_> = self.client.poll_output().collect();
+        let mut disconnect_reason = None;
+
+        for output in outputs {
+            match output {
+                Output::Packet(packet) => {
+                    // Nobody is interested in packets anymore, but the
+                    // connection is kept until the sink is closed
+                    let _ = self.packets_tx.send(packet);
+                }
+                Output::Send(data) => match self.socket.send(&data).await {
+                    // Lost datagrams are resent if they are reliable
+                    Err(error) if !is_transient(&error) => return Err(error.into()),
+                    _ => {}
+                },
+                Output::StateChanged(state) => {
+                    self.state_tx.send_replace(state);
+                }
+                Output::Disconnected(reason) => disconnect_reason = Some(reason),
+                Output::InvalidPacket(_) | Output::PasswordMismatch | Output::None => {}
+            }
+        }
+
+        Ok(disconnect_reason)
+    }
+}
@@ -0,0 +1,308 @@
+//! Password authentication. Minetest uses SRP-6a, so the password itself
+//! never leaves the client.
+
+use std::marker::PhantomData;
+
+use bitflags::bitflags;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+/// 1024-bit group from RFC 5054, appendix A.
+const NG_1024: &str = concat!(
+    "EEAF0AB9ADB38DD69C33F80AFA8FC5E86072618775FF3C0B9EA2314C9C256576",
+    "D674DF7496EA81D3383B4813D692C6E0E0D5D8E250B98BE48E495C1D6089DAD1",
+    "5DC7D7B46154D6B6CE8EF4AD69B15D4982559B297BCF1885C529F566660E57EC",
+    "68EDBC3C05726CC02FD4CBF4976EAA9AFD5138FE8376435B9FC61D2FC0EB06E3",
+);
+
+/// 2048-bit group from RFC 5054, appendix A.
+const NG_2048: &str = concat!(
+    "AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050",
+    "A37329CBB4A099ED8193E0757767A13DD52312AB4B03310DCD7F48A9DA04FD50",
+    "E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9962F0B93B8",
+    "55F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773B",
+    "CA97B43A23FB801676BD207A436C6481F1D2B9078717461A5B9D32E688F87748",
+    "544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52FB3786160279004E57AE6",
+    "AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB6",
+    "94B5C803D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73",
+);
+
+/// Size of the private ephemeral value, in bytes.
+pub const PRIVATE_EPHEMERAL_SIZE: usize = 32;
+/// Size of the salt generated when registering, in bytes.
+pub const SALT_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthMechanisms(u32);
+
+bitflags! {
+    impl AuthMechanisms: u32 {
+        /// Hashed password is sent in plain text. Only used by ancient
+        /// servers.
+        const LEGACY_PASSWORD = 1 << 0;
+        const SRP             = 1 << 1;
+        /// Account doesn't exist yet, and the client has to register it.
+        const FIRST_SRP       = 1 << 2;
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SrpError {
+    #[error("server sent an invalid public ephemeral value")]
+    InvalidPublicEphemeral,
+    #[error("scrambling parameter is zero")]
+    InvalidScramblingParameter,
+}
+
+#[derive(Debug, Clone)]
+pub struct SrpGroup {
+    pub n: BigUint,
+    pub g: BigUint,
+}
+
+impl SrpGroup {
+    /// The group RFC 5054 test vectors use.
+    pub fn ng_1024() -> Self {
+        Self {
+            n: BigUint::parse_bytes(NG_1024.as_bytes(), 16).unwrap(),
+            g: BigUint::from(2u32),
+        }
+    }
+
+    /// The group Minetest uses.
+    pub fn ng_2048() -> Self {
+        Self {
+            n: BigUint::parse_bytes(NG_2048.as_bytes(), 16).unwrap(),
+            g: BigUint::from(2u32),
+        }
+    }
+
+    /// Length of `N` in bytes, which values are padded to before hashing.
+    fn len(&self) -> usize {
+        self.n.bits().div_ceil(8) as usize
+    }
+
+    fn pad(&self, value: &BigUint) -> Vec<u8> {
+        let bytes = value.to_bytes_be();
+        let mut padded = vec![0; self.len().saturating_sub(bytes.len())];
+        padded.extend(bytes);
+        padded
+    }
+}
+
+/// Session key and proof of knowing it, which is sent to the server.
+#[derive(Debug, Clone)]
+pub struct SrpSecrets {
+    pub key: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+fn hash<D: Digest>(parts: &[&[u8]]) -> Vec<u8> {
+    let mut digest = D::new();
+    for part in parts {
+        digest.update(part);
+    }
+    digest.finalize().to_vec()
+}
+
+/// Private key `x` derived from the password. Usernames are case-insensitive,
+/// so the lowercased one is used.
+fn private_key<D: Digest>(username: &str, password: &str, salt: &[u8]) -> BigUint {
+    let identity = username.to_ascii_lowercase();
+    let inner = hash::<D>(&[identity.as_bytes(), b":", password.as_bytes()]);
+    BigUint::from_bytes_be(&hash::<D>(&[salt, &inner]))
+}
+
+/// Computes the verifier `v` which the server stores instead of the password.
+pub fn generate_verifier<D: Digest>(group: &SrpGroup, username: &str, password: &str, salt: &[u8]) -> Vec<u8> {
+    let x = private_key::<D>(username, password, salt);
+    group.g.modpow(&x, &group.n).to_bytes_be()
+}
+
+/// Client side of the SRP-6a exchange.
+///
+/// The verifier is derived from the lowercased username, but the username
+/// is used as is in the proof, matching what Minetest does.
+pub struct SrpClient<D = Sha256> {
+    group: SrpGroup,
+    username: String,
+    password: String,
+    a: BigUint,
+    public_ephemeral: BigUint,
+    _digest: PhantomData<D>,
+}
+
+impl<D: Digest> SrpClient<D> {
+    /// Creates a client with the private ephemeral value `a`, which must be
+    /// random.
+    pub fn new(group: SrpGroup, username: impl Into<String>, password: impl Into<String>, a: &[u8]) -> Self {
+        let a = BigUint::from_bytes_be(a);
+        let public_ephemeral = group.g.modpow(&a, &group.n);
+
+        Self {
+            group,
+            username: username.into(),
+            password: password.into(),
+            a,
+            public_ephemeral,
+            _digest: PhantomData,
+        }
+    }
+
+    /// `A`, which is sent to the server to start the exchange.
+    pub fn public_ephemeral(&self) -> Vec<u8> {
+        self.public_ephemeral.to_bytes_be()
+    }
+
+    /// Computes the session key from the salt and `B` sent by the server.
+    pub fn process_challenge(&self, salt: &[u8], b: &[u8]) -> Result<SrpSecrets, SrpError> {
+        let SrpGroup { n, g } = &self.group;
+        let a_pub = &self.public_ephemeral;
+        let b_pub = BigUint::from_bytes_be(b);
+
+        if (&b_pub % n).bits() == 0 {
+            return Err(SrpError::InvalidPublicEphemeral);
+        }
+
+        let u = BigUint::from_bytes_be(&hash::<D>(&[&self.group.pad(a_pub), &self.group.pad(&b_pub)]));
+        if u.bits() == 0 {
+            return Err(SrpError::InvalidScramblingParameter);
+        }
+
+        let k = BigUint::from_bytes_be(&hash::<D>(&[&n.to_bytes_be(), &self.group.pad(g)]));
+        let x = private_key::<D>(&self.username, &self.password, salt);
+        let v = g.modpow(&x, n);
+
+        // S = (B - kv) ^ (a + ux)
+        let base = (&b_pub + n - (k * v) % n) % n;
+        let premaster_secret = base.modpow(&(&self.a + u * x), n);
+        let key = hash::<D>(&[&premaster_secret.to_bytes_be()]);
+
+        // M = H(H(N) xor H(g), H(I), s, A, B, K)
+        let hash_n = hash::<D>(&[&n.to_bytes_be()]);
+        let hash_g = hash::<D>(&[&g.to_bytes_be()]);
+        let hash_ng: Vec<u8> = hash_n.iter().zip(hash_g).map(|(n, g)| n ^ g).collect();
+        let hash_i = hash::<D>(&[self.username.as_bytes()]);
+        let proof = hash::<D>(&[
+            &hash_ng,
+            &hash_i,
+            salt,
+            &a_pub.to_bytes_be(),
+            &b_pub.to_bytes_be(),
+            &key,
+        ]);
+
+        Ok(SrpSecrets { key, proof })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1::Sha1;
+
+    fn hex(s: &str) -> Vec<u8> {
+        BigUint::parse_bytes(s.as_bytes(), 16).unwrap().to_bytes_be()
+    }
+
+    /// Test vectors from RFC 5054, appendix B.
+    #[test]
+    fn rfc_5054_vectors() {
+        let group = SrpGroup::ng_1024();
+        let salt = hex("BEB25379D1A8581EB5A727673A2441EE");
+        let a = hex("60975527035CF2AD1989806F0407210BC81EDC04E2762A56AFD529DDDA2D4393");
+        let b = hex(concat!(
+            "BD0C61512C692C0CB6D041FA01BB152D4916A1E77AF46AE105393011BAF38964",
+            "DC46A0670DD125B95A981652236F99D9B681CBF87837EC996C6DA04453728610",
+            "D0C6DDB58B318885D7D82C7F8DEB75CE7BD4FBAA37089E6F9C6059F388838E7A",
+            "00030B331EB76840910440B1B27AAEAEEB4012B7D7665238A8E3FB004B117B58",
+        ));
+        let verifier = hex(concat!(
+            "7E273DE8696FFC4F4E337D05B4B375BEB0DDE1569E8FA00A9886D8129BADA1F1",
+            "822223CA1A605B530E379BA4729FDC59F105B4787E5186F5C671085A1447B52A",
+            "48CF1970B4FB6F8400BBF4CEBFBB168152E08AB5EA53D15C1AFF87B2B9DA6E04",
+            "E058AD51CC72BFC9033B564E26480D78E955A5E29E7AB245DB2BE315E2099AFB",
+        ));
+        let public_ephemeral = hex(concat!(
+            "61D5E490F6F1B79547B0704C436F523DD0E560F0C64115BB72557EC44352E890",
+            "3211C04692272D8B2D1A5358A2CF1B6E0BFCF99F921530EC8E39356179EAE45E",
+            "42BA92AEACED825171E1E8B9AF6D9C03E1327F44BE087EF06530E69F66615261",
+            "EEF54073CA11CF5858F0EDFDFE15EFEAB349EF5D76988A3672FAC47B0769447B",
+        ));
+        let premaster_secret = hex(concat!(
+            "B0DC82BABCF30674AE450C0287745E7990A3381F63B387AAF271A10D233861E3",
+            "59B48220F7C4693C9AE12B0A6F67809F0876E2D013800D6C41BB59B6D5979B5C",
+            "00A172B4A2A5903A0BDCAF8A709585EB2AFAFA8F3499B200210DCC1F10EB3394",
+            "3CD67FC88A2F39A4BE5BEC4EC0A3212DC346D7E474B29EDE8A469FFECA686E5A",
+        ));
+
+        assert_eq!(
+            generate_verifier::<Sha1>(&group, "alice", "password123", &salt),
+            verifier
+        );
+
+        let client = SrpClient::<Sha1>::new(group, "alice", "password123", &a);
+        assert_eq!(client.public_ephemeral(), public_ephemeral);
+
+        // The RFC stops at the premaster secret, which the key is hashed from
+        let secrets = client.process_challenge(&salt, &b).unwrap();
+        assert_eq!(secrets.key, hash::<Sha1>(&[&premaster_secret]));
+    }
+
+    /// Computed independently following Minetest's `srp.cpp`, for a username
+    /// which differs from the lowercased one the verifier is based on.
+    #[test]
+    fn minetest_known_answer() {
+        let group = SrpGroup::ng_2048();
+        let salt: Vec<u8> = (0x10..0x20).collect();
+        let a: Vec<u8> = (0x20..0x40).collect();
+        let b = hex(concat!(
+            "6D23A03ACECD72E468D032F991311AE3B99583C03F7ED081F4EE3B24F3751732",
+            "9C7EF28548F22E7492D356BFF112193E39628D9760B2E4AE89DDC30D7C83A1E3",
+            "B1709042900DD471B4A9255E9A067282497240AE81EB3B44F38FE09C2A8D838C",
+            "615BC61A41E7CA9FB0701288A8720E1B530FF73606A3B6B6C952D0787A788E61",
+            "0C4F04E962CD60C07FB6D5C148C5E1245A7AFBE1A4536F4867AE13225CA742A1",
+            "EEBFF872E9D9D3DA1C8A08F56AD2D1E1B60D82D8CED9732C455E81AB96BA5E14",
+            "8C5A50853F453D6E2949F4D37C5C2428C3028AE2EACA299AB0B15E73DE1FB454",
+            "C3E366719D3B289660C60C347DE1E18FA6234DC816182C1B0726DBDA1BF3A847",
+        ));
+        let verifier = hex(concat!(
+            "18E494ADA3AA41422D3BB84B4544AF80E8884352DB822A9EC60D0C368EE4A02A",
+            "1D6B48F4189308F2E74A0B64F6E0A84F829E87D9C15BC6CEF7E01F61643B1747",
+            "07A170C7C7B94C98C2A8C809B38AA93680367D9C880B20101F5CE73EB243BF0E",
+            "6A609510DE22E7051CD63B0B93DF02114421CBAAC8DF07BAF43FB254826D9C7A",
+            "5F43036D522183C27B93D3A37C4FC0E7287D1667C1D5437016102F4E8EF19F00",
+            "58AE973B0A45121CBA4B321AD7295D2A2379D15E0E9DDDF0E9D27FC5D1AABA88",
+            "FB305592C05F268F312479AA3046381515F7C45D3ED440D765A1692958DEA23D",
+            "8197147E13C0F366DD280C99816F68F3DB4D4FC8BEE6F34DCD91E640D9F8EFCC",
+        ));
+        let public_ephemeral = hex(concat!(
+            "83224E652A1C7A26A5F542C21D1719AF3E03A36E7DE68F083BC92E166B0824DD",
+            "7F0FAC89407304DEDC1AE0ED531CA2F9FACE65C16BE0084459CBFCDDA3D1C2B5",
+            "338FC32B1185AA84854A41173A256B69C410B1E8370FF9F71414CCCBCA0A6713",
+            "C29EC3C1B9FED765C250DAA320989A0F65BEA8DD1DBD06C8BE810FFD7E8B22B1",
+            "315DDF2DED33131C985164A0A80B911C9C3F74FD6E1B54DF2CE1C28B45250459",
+            "A956515691765D4771AA95604A42A7BA6A29474FAD478FE7B4C87782D3DF2631",
+            "BA424F0569E95621DED79DFF3D3D563D6DF73E03491710ABF46F93722E7C3A10",
+            "1D50E4B85851D1CE63D0225B0D002D5956C1D6F1E5430ABFB1F6AEB778F757B0",
+        ));
+
+        assert_eq!(
+            generate_verifier::<Sha256>(&group, "PlayerOne", "hunter2", &salt),
+            verifier
+        );
+
+        let client = SrpClient::<Sha256>::new(group, "PlayerOne", "hunter2", &a);
+        assert_eq!(client.public_ephemeral(), public_ephemeral);
+
+        let secrets = client.process_challenge(&salt, &b).unwrap();
+        assert_eq!(
+            secrets.key,
+            hex("ECB1659CAF3CDAFDC1E19DDFE058DEE7CA4575857449B255947306717F5BE809")
+        );
+        assert_eq!(
+            secrets.proof,
+            hex("ABB256568FDB095D789E2F826B0C3B3C37EA6A3572957B4A405B41853657A41C")
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Recording of raw datagrams, e.g. for inspecting a session in Wireshark
+//! or replaying it later.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{Error, Result};
+
+const PCAP_MAGIC: u32 = 0xA1B2C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B23C4D;
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_SNAPLEN: u32 = 0xFFFF;
+/// Largest record accepted when reading, whatever the capture claims. Same
+/// as the largest snapshot length tcpdump and Wireshark use.
+const PCAP_MAX_RECORD_SIZE: usize = 0x40000;
+/// Packets start directly with an IPv4 or IPv6 header.
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const ETHERNET_HEADER_SIZE: usize = 14;
+const LINUX_SLL_HEADER_SIZE: usize = 16;
+
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const UDP_HEADER_SIZE: usize = 8;
+const IP_PROTOCOL_UDP: u8 = 17;
+const TTL: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the remote side.
+    Incoming,
+    /// Sent to the remote side.
+    Outgoing,
+}
+
+/// Receives every datagram the client sends or receives.
+pub trait Recorder: Send {
+    fn record(&mut self, direction: Direction, data: &[u8], time: Instant) -> Result<()>;
+}
+
+/// Writes datagrams in the classic pcap format. Since only UDP payloads are
+/// known, IP and UDP headers are made up from the given addresses.
+pub struct PcapWriter<W> {
+    writer: W,
+    local: SocketAddr,
+    remote: SocketAddr,
+    /// Wall clock time corresponding to `started_at`, since pcap timestamps
+    /// can't be derived from an `Instant` alone.
+    started_at: (Instant, SystemTime),
+    ip_id: u16,
+}
+
+impl<W: Write> PcapWriter<W> {
+    pub fn new(mut writer: W, local: SocketAddr, remote: SocketAddr) -> Result<Self> {
+        writer.write_all(&PCAP_MAGIC.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MAJOR.to_le_bytes())?;
+        writer.write_all(&PCAP_VERSION_MINOR.to_le_bytes())?;
+        // Timezone offset and timestamp accuracy
+        writer.write_all(&0i32.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(&PCAP_SNAPLEN.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            local,
+            remote,
+            started_at: (Instant::now(), SystemTime::now()),
+            ip_id: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn timestamp(&self, time: Instant) -> SystemTime {
+        let (instant, system_time) = self.started_at;
+
+        match time.checked_duration_since(instant) {
+            Some(elapsed) => system_time + elapsed,
+            None => system_time - instant.duration_since(time),
+        }
+    }
+
+    fn write_packet(&mut self, from: SocketAddr, to: SocketAddr, data: &[u8], time: Instant) -> Result<()> {
+        let mut packet = match (from.ip(), to.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                self.ip_id = self.ip_id.wrapping_add(1);
+                ipv4_header(src.octets(), dst.octets(), self.ip_id, UDP_HEADER_SIZE + data.len())
+            }
+            (src, dst) => ipv6_header(to_ipv6(src), to_ipv6(dst), UDP_HEADER_SIZE + data.len()),
+        };
+
+        packet.extend(udp_header(from, to, data));
+        packet.extend_from_slice(data);
+
+        let len = packet.len() as u32;
+        let timestamp = self.timestamp(time).duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        self.writer.write_all(&(timestamp.as_secs() as u32).to_le_bytes())?;
+        self.writer.write_all(&timestamp.subsec_micros().to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&packet)?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Recorder for PcapWriter<W> {
+    fn record(&mut self, direction: Direction, data: &[u8], time: Instant) -> Result<()> {
+        let (from, to) = match direction {
+            Direction::Incoming => (self.remote, self.local),
+            Direction::Outgoing => (self.local, self.remote),
+        };
+
+        self.write_packet(from, to, data, time)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+/// Internet checksum over the given chunks, as used by IPv4 and UDP.
+fn checksum<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> u16 {
+    let mut sum = 0u32;
+    let mut odd_byte = None;
+
+    for &byte in chunks.into_iter().flatten() {
+        match odd_byte.take() {
+            None => odd_byte = Some(byte),
+            Some(high) => sum += u16::from_be_bytes([high, byte]) as u32,
+        }
+    }
+
+    if let Some(high) = odd_byte {
+        sum += u16::from_be_bytes([high, 0]) as u32;
+    }
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+fn ipv4_header(src: [u8; 4], dst: [u8; 4], id: u16, payload_len: usize) -> Vec<u8> {
+    let total_len = (IPV4_HEADER_SIZE + payload_len) as u16;
+
+    let mut header = Vec::with_capacity(IPV4_HEADER_SIZE);
+    header.extend([0x45, 0]);
+    header.extend(total_len.to_be_bytes());
+    header.extend(id.to_be_bytes());
+    // Don't fragment
+    header.extend(0x4000u16.to_be_bytes());
+    header.extend([TTL, IP_PROTOCOL_UDP]);
+    header.extend([0, 0]);
+    header.extend(src);
+    header.extend(dst);
+
+    let sum = checksum([header.as_slice()]);
+    header[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    header
+}
+
+fn ipv6_header(src: [u8; 16], dst: [u8; 16], payload_len: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(IPV6_HEADER_SIZE);
+    header.extend(0x6000_0000u32.to_be_bytes());
+    header.extend((payload_len as u16).to_be_bytes());
+    header.extend([IP_PROTOCOL_UDP, TTL]);
+    header.extend(src);
+    header.extend(dst);
+
+    header
+}
+
+fn udp_header(from: SocketAddr, to: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let len = (UDP_HEADER_SIZE + data.len()) as u16;
+
+    let mut header = Vec::with_capacity(UDP_HEADER_SIZE);
+    header.extend(from.port().to_be_bytes());
+    header.extend(to.port().to_be_bytes());
+    header.extend(len.to_be_bytes());
+    header.extend([0, 0]);
+
+    // Pseudo header of the IP layer is covered by the checksum as well
+    let mut pseudo_header = Vec::new();
+    match (from.ip(), to.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            pseudo_header.extend(src.octets());
+            pseudo_header.extend(dst.octets());
+            pseudo_header.extend([0, IP_PROTOCOL_UDP]);
+            pseudo_header.extend(len.to_be_bytes());
+        }
+        (src, dst) => {
+            pseudo_header.extend(to_ipv6(src));
+            pseudo_header.extend(to_ipv6(dst));
+            pseudo_header.extend((len as u32).to_be_bytes());
+            pseudo_header.extend([0, 0, 0, IP_PROTOCOL_UDP]);
+        }
+    }
+
+    let sum = match checksum([pseudo_header.as_slice(), &header, data]) {
+        // Zero means "no checksum", so it's sent as all ones instead
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    header[6..8].copy_from_slice(&sum.to_be_bytes());
+
+    header
+}
+
+/// Datagram read back from a capture.
+#[derive(Debug, Clone)]
+pub struct CapturedDatagram {
+    pub direction: Direction,
+    /// Time since the first datagram of the capture.
+    pub time: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Extracts UDP payload and its endpoints from a captured IP packet.
+fn parse_ip_packet(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src, dst, protocol, payload): (IpAddr, IpAddr, _, _) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = (packet[0] & 0x0F) as usize * 4;
+            let total_len = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as usize;
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+
+            (
+                Ipv4Addr::from(src).into(),
+                Ipv4Addr::from(dst).into(),
+                *packet.get(9)?,
+                packet.get(header_len..total_len)?,
+            )
+        }
+        6 => {
+            // Extension headers are not supported
+            let payload_len = u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]) as usize;
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+
+            (
+                Ipv6Addr::from(src).into(),
+                Ipv6Addr::from(dst).into(),
+                *packet.get(6)?,
+                packet.get(IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + payload_len)?,
+            )
+        }
+        _ => return None,
+    };
+
+    if protocol != IP_PROTOCOL_UDP {
+        return None;
+    }
+
+    let src_port = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]);
+    let dst_port = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    let len = u16::from_be_bytes([*payload.get(4)?, *payload.get(5)?]) as usize;
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload.get(UDP_HEADER_SIZE..len)?,
+    ))
+}
+
+/// Reads datagrams of a single connection from a pcap capture.
+///
+/// The client is assumed to be the side which sent the first UDP datagram,
+/// since it's the one who initiates the connection. Traffic of other
+/// connections is skipped.
+///
+/// The whole capture is read into memory. Only the size of single records is
+/// bounded, [`mtt_serialize::Limits`] apply once datagrams are fed into a
+/// client.
+pub fn read_pcap<R: Read>(mut r: R) -> Result<Vec<CapturedDatagram>> {
+    let mut header = [0; PCAP_HEADER_SIZE];
+    r.read_exact(&mut header)?;
+
+    let magic: [u8; 4] = header[0..4].try_into().unwrap();
+    let magic_le = u32::from_le_bytes(magic);
+    let magic_be = u32::from_be_bytes(magic);
+
+    let (big_endian, nanos) = match (magic_le, magic_be) {
+        (PCAP_MAGIC, _) => (false, false),
+        (PCAP_MAGIC_NANOS, _) => (false, true),
+        (_, PCAP_MAGIC) => (true, false),
+        (_, PCAP_MAGIC_NANOS) => (true, true),
+        // Including pcapng
+        _ => return Err(Error::UnsupportedCapture),
+    };
+
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = bytes.try_into().unwrap();
+        match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    };
+
+    let link_header_size = match read_u32(&header[20..24]) {
+        LINKTYPE_RAW => 0,
+        LINKTYPE_ETHERNET => ETHERNET_HEADER_SIZE,
+        LINKTYPE_LINUX_SLL => LINUX_SLL_HEADER_SIZE,
+        link_type => return Err(Error::UnsupportedLinkType(link_type)),
+    };
+
+    // Records are never longer than the snapshot length, which some writers
+    // leave at zero
+    let max_len = match read_u32(&header[16..20]) as usize {
+        0 => PCAP_MAX_RECORD_SIZE,
+        snaplen => snaplen.min(PCAP_MAX_RECORD_SIZE),
+    };
+
+    let mut endpoints = None;
+    let mut first_timestamp = None;
+    let mut datagrams = Vec::new();
+
+    loop {
+        let mut record_header = [0; PCAP_RECORD_HEADER_SIZE];
+        match r.read_exact(&mut record_header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+
+        let secs = read_u32(&record_header[0..4]) as u64;
+        let fraction = read_u32(&record_header[4..8]);
+        let len = read_u32(&record_header[8..12]) as usize;
+        if len > max_len {
+            return Err(Error::LimitExceeded {
+                what: "capture record",
+                len,
+                limit: max_len,
+            });
+        }
+
+        let mut packet = vec![0; len];
+        r.read_exact(&mut packet)?;
+
+        let timestamp = match nanos {
+            true => Duration::new(secs, fraction),
+            false => Duration::new(secs, 0) + Duration::from_micros(fraction as u64),
+        };
+
+        let Some((from, to, data)) = packet.get(link_header_size..).and_then(parse_ip_packet) else {
+            continue;
+        };
+
+        let (client, server) = *endpoints.get_or_insert((from, to));
+        let direction = if (from, to) == (client, server) {
+            Direction::Outgoing
+        } else if (from, to) == (server, client) {
+            Direction::Incoming
+        } else {
+            continue;
+        };
+
+        let first_timestamp = *first_timestamp.get_or_insert(timestamp);
+
+        datagrams.push(CapturedDatagram {
+            direction,
+            time: timestamp.saturating_sub(first_timestamp),
+            data: data.to_vec(),
+        });
+    }
+
+    Ok(datagrams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_longer_than_the_snapshot_length_are_rejected() {
+        let local = "127.0.0.1:40000".parse().unwrap();
+        let remote = "127.0.0.1:30000".parse().unwrap();
+        let mut capture = PcapWriter::new(Vec::new(), local, remote).unwrap().into_inner();
+
+        // Timestamp, then captured and original length
+        capture.extend([0; 8]);
+        capture.extend(u32::MAX.to_le_bytes());
+        capture.extend(u32::MAX.to_le_bytes());
+
+        let error = read_pcap(capture.as_slice()).unwrap_err();
+        assert!(matches!(error, Error::LimitExceeded { limit: 0xFFFF, .. }));
+    }
+}
@@ -0,0 +1,32 @@
+use crate::reliable::{ReliableReceiver, ReliableSender};
+use crate::split::SplitBuffer;
+
+pub const CHANNEL_COUNT: usize = 3;
+
+/// Initial seqnum of every reliable channel. Both sides have to agree on it,
+/// so it must match the value used by Minetest.
+pub const SEQNUM_INITIAL: u16 = 65500;
+
+pub struct Channel {
+    pub outgoing: ReliableSender,
+    pub incoming: ReliableReceiver,
+    pub splits: SplitBuffer,
+    next_split_seqnum: u16,
+}
+
+impl Channel {
+    pub fn new() -> Self {
+        Self {
+            outgoing: ReliableSender::new(SEQNUM_INITIAL),
+            incoming: ReliableReceiver::new(SEQNUM_INITIAL),
+            splits: SplitBuffer::new(),
+            next_split_seqnum: SEQNUM_INITIAL,
+        }
+    }
+
+    pub fn next_split_seqnum(&mut self) -> u16 {
+        let seqnum = self.next_split_seqnum;
+        self.next_split_seqnum = self.next_split_seqnum.wrapping_add(1);
+        seqnum
+    }
+}
@@ -1,9 +1,13 @@
 use glam::{Vec3, I16Vec3};
-use mtt_core::world::Block;
+use mtt_core::inventory::InventoryUpdate;
+use mtt_core::object::command::ObjectCommand;
+use mtt_core::object::{Object, ObjectInitData};
+use mtt_core::world::{Block, WorldState};
 use mtt_macros::{packet, Serialize};
-use mtt_serialize::{RawBytes16, RawBytes32, Serialize, StringSerializeExt};
+use mtt_serialize::{Error, RawBytes16, RawBytes32, Result, Serialize, StringSerializeExt};
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::fmt;
+use std::io::{Cursor, Read, Write};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Hello {
@@ -22,6 +26,150 @@ pub struct AuthAccept {
     pub supported_sudo_auth_methods: u32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessDeniedReason {
+    WrongPassword,
+    UnexpectedData,
+    Singleplayer,
+    WrongVersion,
+    WrongCharsInName,
+    WrongName,
+    TooManyUsers,
+    EmptyPassword,
+    AlreadyConnected,
+    ServerFail,
+    /// Explained by the custom reason only.
+    Custom,
+    Shutdown,
+    Crash,
+    Unknown(u8),
+}
+
+impl AccessDeniedReason {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0 => AccessDeniedReason::WrongPassword,
+            1 => AccessDeniedReason::UnexpectedData,
+            2 => AccessDeniedReason::Singleplayer,
+            3 => AccessDeniedReason::WrongVersion,
+            4 => AccessDeniedReason::WrongCharsInName,
+            5 => AccessDeniedReason::WrongName,
+            6 => AccessDeniedReason::TooManyUsers,
+            7 => AccessDeniedReason::EmptyPassword,
+            8 => AccessDeniedReason::AlreadyConnected,
+            9 => AccessDeniedReason::ServerFail,
+            10 => AccessDeniedReason::Custom,
+            11 => AccessDeniedReason::Shutdown,
+            12 => AccessDeniedReason::Crash,
+            code => AccessDeniedReason::Unknown(code),
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            AccessDeniedReason::WrongPassword => 0,
+            AccessDeniedReason::UnexpectedData => 1,
+            AccessDeniedReason::Singleplayer => 2,
+            AccessDeniedReason::WrongVersion => 3,
+            AccessDeniedReason::WrongCharsInName => 4,
+            AccessDeniedReason::WrongName => 5,
+            AccessDeniedReason::TooManyUsers => 6,
+            AccessDeniedReason::EmptyPassword => 7,
+            AccessDeniedReason::AlreadyConnected => 8,
+            AccessDeniedReason::ServerFail => 9,
+            AccessDeniedReason::Custom => 10,
+            AccessDeniedReason::Shutdown => 11,
+            AccessDeniedReason::Crash => 12,
+            AccessDeniedReason::Unknown(code) => *code,
+        }
+    }
+
+    /// Message Minetest shows for the reason.
+    pub fn message(&self) -> &'static str {
+        match self {
+            AccessDeniedReason::WrongPassword => "Invalid password",
+            AccessDeniedReason::UnexpectedData => {
+                "Your client sent something the server didn't expect. Try reconnecting or updating your client."
+            }
+            AccessDeniedReason::Singleplayer => "The server is running in singleplayer mode. You cannot connect.",
+            AccessDeniedReason::WrongVersion => "Your client's version is not supported.",
+            AccessDeniedReason::WrongCharsInName => "Player name contains disallowed characters",
+            AccessDeniedReason::WrongName => "Player name not allowed",
+            AccessDeniedReason::TooManyUsers => "Too many users",
+            AccessDeniedReason::EmptyPassword => "Empty passwords are disallowed. Set a password and try again.",
+            AccessDeniedReason::AlreadyConnected => {
+                "Another client is connected with this name. If your client closed unexpectedly, try again in a minute."
+            }
+            AccessDeniedReason::ServerFail => "Internal server error",
+            AccessDeniedReason::Custom => "",
+            AccessDeniedReason::Shutdown => "Server shutting down",
+            AccessDeniedReason::Crash => "The server has experienced an internal error.",
+            AccessDeniedReason::Unknown(_) => "Unknown disconnect reason.",
+        }
+    }
+}
+
+/// Sent before the server drops the client, e.g. on a kick or a wrong
+/// password.
+#[derive(Debug, Clone)]
+pub struct AccessDenied {
+    pub reason: AccessDeniedReason,
+    /// Overrides the standard message of the reason if not empty.
+    pub custom_reason: String,
+    /// Whether the server suggests connecting again, e.g. after a restart.
+    pub reconnect: bool,
+}
+
+impl fmt::Display for AccessDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.custom_reason.is_empty() {
+            f.write_str(self.reason.message())
+        } else {
+            f.write_str(&self.custom_reason)
+        }
+    }
+}
+
+impl Serialize for AccessDenied {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.reason.code().serialize(w)?;
+        self.custom_reason.serialize(w)?;
+        (self.reconnect as u8).serialize(w)
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let reason = AccessDeniedReason::from_code(u8::deserialize(r)?);
+
+        // Custom reason and reconnect flag are optional
+        let mut rest = Vec::new();
+        r.read_to_end(&mut rest)?;
+        let r = &mut Cursor::new(rest.as_slice());
+
+        let (custom_reason, reconnect) = if rest.is_empty() {
+            (String::new(), false)
+        } else {
+            let custom_reason = String::deserialize(r)?;
+            let reconnect = if (r.position() as usize) < rest.len() {
+                u8::deserialize(r)? & 1 != 0
+            } else {
+                false
+            };
+
+            (custom_reason, reconnect)
+        };
+
+        // Like Minetest, too many users always implies reconnecting, and
+        // servers send only the code byte for it
+        let reconnect = reconnect || reason == AccessDeniedReason::TooManyUsers;
+
+        Ok(Self {
+            reason,
+            custom_reason,
+            reconnect,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct BlockData {
     pub position: I16Vec3,
@@ -29,7 +177,9 @@ pub struct BlockData {
 }
 
 #[derive(Debug, Clone, Serialize)]
-pub struct Inventory {}
+pub struct Inventory {
+    pub update: InventoryUpdate,
+}
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TimeOfDay {
@@ -53,11 +203,15 @@ pub struct ChatMessage {
 }
 
 impl Serialize for ChatMessage {
-    fn serialize<W: Write>(&self, _w: &mut W) -> anyhow::Result<()> {
-        todo!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.version.serialize(w)?;
+        self.ty.serialize(w)?;
+        self.sender.serialize_utf16(w)?;
+        self.message.serialize_utf16(w)?;
+        self.time.serialize(w)
     }
 
-    fn deserialize<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         Ok(ChatMessage {
             version: u8::deserialize(r)?,
             ty: u8::deserialize(r)?,
@@ -69,13 +223,58 @@ impl Serialize for ChatMessage {
 }
 
 #[derive(Debug, Clone, Serialize)]
-pub struct ActiveObjectRemoveAdd {
-    // TODO
+pub struct AddedObject {
+    pub id: u16,
+    pub init: ObjectInitData,
 }
 
 #[derive(Debug, Clone, Serialize)]
+pub struct ActiveObjectRemoveAdd {
+    pub removed: Vec<u16>,
+    pub added: Vec<AddedObject>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectMessage {
+    pub id: u16,
+    pub command: ObjectCommand,
+}
+
+#[derive(Debug, Clone)]
 pub struct ActiveObjectMessages {
-    // TODO
+    pub messages: Vec<ObjectMessage>,
+}
+
+impl Serialize for ActiveObjectMessages {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        for message in &self.messages {
+            let mut data = Vec::new();
+            message.command.serialize(&mut data)?;
+
+            message.id.serialize(w)?;
+            RawBytes16(data).serialize(w)?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        // Messages continue until the end of the packet
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        let r = &mut Cursor::new(data.as_slice());
+
+        let mut messages = Vec::new();
+        while (r.position() as usize) < data.len() {
+            let id = u16::deserialize(r)?;
+            let message = RawBytes16::deserialize(r)?.0;
+            let command = ObjectCommand::deserialize(&mut Cursor::new(message))?;
+
+            messages.push(ObjectMessage { id, command });
+        }
+
+        Ok(Self { messages })
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -98,11 +297,25 @@ pub struct Media {
 }
 
 impl Serialize for Media {
-    fn serialize<W: Write>(&self, _w: &mut W) -> anyhow::Result<()> {
-        todo!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.bunch_count.serialize(w)?;
+        self.bunch_id.serialize(w)?;
+
+        let file_count: u32 = self.files.len().try_into().map_err(|_| Error::TooLong {
+            what: "media bunch",
+            len: self.files.len(),
+        })?;
+        file_count.serialize(w)?;
+
+        for (name, data) in &self.files {
+            name.serialize(w)?;
+            RawBytes32(data.clone()).serialize(w)?;
+        }
+
+        Ok(())
     }
 
-    fn deserialize<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let bunch_count = u16::deserialize(r)?;
         let bunch_id = u16::deserialize(r)?;
         let file_count = u32::deserialize(r)?;
@@ -135,16 +348,28 @@ pub struct AnnounceMedia {
 }
 
 impl Serialize for AnnounceMedia {
-    fn serialize<W: Write>(&self, _w: &mut W) -> anyhow::Result<()> {
-        todo!()
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        let count: u16 = self.digests.len().try_into().map_err(|_| Error::TooLong {
+            what: "media announcement",
+            len: self.digests.len(),
+        })?;
+        count.serialize(w)?;
+
+        for (name, digest) in &self.digests {
+            name.serialize(w)?;
+            base64::encode(digest).serialize(w)?;
+        }
+
+        self.content_servers.join(",").serialize(w)
     }
 
-    fn deserialize<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let mut digests = HashMap::new();
         let count = u16::deserialize(r)?;
         for _ in 0..count {
             let name = String::deserialize(r)?;
-            let digest = base64::decode(String::deserialize(r)?)?;
+            let digest =
+                base64::decode(String::deserialize(r)?).map_err(|_| Error::Invalid { what: "media digest" })?;
             digests.insert(name, digest);
         }
 
@@ -167,15 +392,92 @@ pub struct Privileges {
     // TODO
 }
 
-#[derive(Debug, Clone, Serialize)]
+fn serialize_long_string<W: Write>(value: &str, w: &mut W) -> Result<()> {
+    RawBytes32(value.as_bytes().to_vec()).serialize(w)
+}
+
+fn deserialize_long_string<R: Read>(r: &mut R) -> Result<String> {
+    Ok(String::from_utf8(RawBytes32::deserialize(r)?.0)?)
+}
+
+/// Formspec of the inventory screen, which can be parsed with
+/// [`mtt_core::formspec::Formspec`].
+#[derive(Debug, Clone)]
 pub struct InventoryFormspec {
-    // TODO
+    pub formspec: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl Serialize for InventoryFormspec {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        serialize_long_string(&self.formspec, w)
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(Self {
+            formspec: deserialize_long_string(r)?,
+        })
+    }
+}
+
+/// Inventory which doesn't belong to a player or a node, e.g. a creative
+/// inventory.
+#[derive(Debug, Clone)]
 pub struct DetachedInventory {
     pub name: String,
-    // TODO
+    /// Inventory is removed if not set.
+    pub update: Option<InventoryUpdate>,
+}
+
+impl Serialize for DetachedInventory {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.name.serialize(w)?;
+        self.update.is_some().serialize(w)?;
+
+        if let Some(update) = &self.update {
+            let data = update.to_string();
+            // Length used to be read by old clients, and isn't used anymore
+            (data.len().min(u16::MAX as usize) as u16).serialize(w)?;
+            w.write_all(data.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let name = String::deserialize(r)?;
+        let keep = bool::deserialize(r)?;
+
+        let update = if keep {
+            let _len = u16::deserialize(r)?;
+            Some(InventoryUpdate::deserialize(r)?)
+        } else {
+            None
+        };
+
+        Ok(Self { name, update })
+    }
+}
+
+/// Form to show to the player. An empty formspec closes the form with the
+/// same name.
+#[derive(Debug, Clone)]
+pub struct ShowFormspec {
+    pub formspec: String,
+    pub form_name: String,
+}
+
+impl Serialize for ShowFormspec {
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        serialize_long_string(&self.formspec, w)?;
+        self.form_name.serialize(w)
+    }
+
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(Self {
+            formspec: deserialize_long_string(r)?,
+            form_name: String::deserialize(r)?,
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -234,6 +536,9 @@ pub enum ClientBound {
     #[id = 0x03]
     AuthAccept(AuthAccept),
 
+    #[id = 0x0A]
+    AccessDenied(AccessDenied),
+
     #[id = 0x20]
     BlockData(BlockData),
 
@@ -282,6 +587,9 @@ pub enum ClientBound {
     #[id = 0x43]
     DetachedInventory(DetachedInventory),
 
+    #[id = 0x44]
+    ShowFormspec(ShowFormspec),
+
     #[id = 0x45]
     Movement(Movement),
 
@@ -303,3 +611,224 @@ pub enum ClientBound {
     #[id = 0x60]
     SrpBytesSB(SrpBytesSB),
 }
+
+impl ClientBound {
+    /// Channel on which the client expects to receive this packet.
+    pub fn channel(&self) -> u8 {
+        match self {
+            ClientBound::BlockData(_) => 2,
+            ClientBound::Media(_) => 2,
+            _ => 0,
+        }
+    }
+
+    /// Updates the world with whatever this packet carries. Packets which
+    /// don't affect the world are ignored.
+    pub fn apply(&self, world: &mut WorldState) {
+        match self {
+            ClientBound::BlockData(block_data) => {
+                world.map.update_or_set(block_data.position, block_data.block.clone());
+            }
+            ClientBound::TimeOfDay(time_of_day) => {
+                world.time = time_of_day.time as f32;
+                world.time_speed = time_of_day.time_speed;
+            }
+            ClientBound::MovePlayer(move_player) => {
+                // Positions are sent in tenths of a node
+                world.player.position = move_player.position / 10.0;
+            }
+            ClientBound::Inventory(inventory) => world.player.inventory.apply(&inventory.update),
+            ClientBound::InventoryFormspec(inventory_formspec) => {
+                world.player.inventory_formspec = inventory_formspec.formspec.clone();
+            }
+            ClientBound::DetachedInventory(detached) => match &detached.update {
+                Some(update) => {
+                    let inventory = world.detached_inventories.entry(detached.name.clone()).or_default();
+                    inventory.apply(update);
+                }
+                None => {
+                    world.detached_inventories.remove(&detached.name);
+                }
+            },
+            ClientBound::ActiveObjectRemoveAdd(remove_add) => {
+                for id in &remove_add.removed {
+                    world.objects.remove(id);
+                }
+
+                for added in &remove_add.added {
+                    if let ObjectInitData::Generic(init) = &added.init {
+                        world.objects.insert(added.id, Object::new(init));
+                    }
+                }
+            }
+            ClientBound::ActiveObjectMessages(messages) => {
+                for message in &messages.messages {
+                    if let Some(object) = world.objects.get_mut(&message.id) {
+                        object.apply(&message.command);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mtt_core::inventory::item::ItemStack;
+    use mtt_core::object::properties::{ObjectProperties, Visual};
+
+    fn decode_access_denied(data: &[u8]) -> AccessDenied {
+        AccessDenied::deserialize(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn access_denied_short_form() {
+        let denied = decode_access_denied(&[6]);
+
+        assert_eq!(denied.reason, AccessDeniedReason::TooManyUsers);
+        assert_eq!(denied.custom_reason, "");
+        assert!(denied.reconnect);
+
+        // Other reasons only reconnect when the server asks to
+        assert!(!decode_access_denied(&[11]).reconnect);
+    }
+
+    #[test]
+    fn access_denied_with_custom_reason_and_reconnect() {
+        let mut data = vec![11];
+        "Restarting".to_string().serialize(&mut data).unwrap();
+        data.push(1);
+        let denied = decode_access_denied(&data);
+
+        assert_eq!(denied.reason, AccessDeniedReason::Shutdown);
+        assert_eq!(denied.custom_reason, "Restarting");
+        assert!(denied.reconnect);
+
+        // Too many users implies reconnecting even without the flag
+        let mut data = vec![6];
+        String::new().serialize(&mut data).unwrap();
+        assert!(decode_access_denied(&data).reconnect);
+    }
+
+    fn decode(data: &[u8]) -> ClientBound {
+        ClientBound::deserialize(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn detached_inventory() {
+        let inventory = "List main 2\nWidth 0\nItem default:dirt 5\nEmpty\nEndInventoryList\nEndInventory\n";
+
+        let mut data = vec![0x00, 0x43];
+        "creative_trash".to_string().serialize(&mut data).unwrap();
+        data.push(1);
+        (inventory.len() as u16).serialize(&mut data).unwrap();
+        data.extend(inventory.as_bytes());
+
+        let packet = decode(&data);
+        let ClientBound::DetachedInventory(ref detached) = packet else {
+            panic!("unexpected packet {packet:?}");
+        };
+        assert_eq!(detached.name, "creative_trash");
+        assert!(detached.update.is_some());
+
+        let mut world = WorldState::new();
+        packet.apply(&mut world);
+        let list = world.detached_inventories["creative_trash"].list("main").unwrap();
+        assert_eq!(list.items, [ItemStack::new("default:dirt", 5), ItemStack::default()]);
+
+        // Removed without an inventory
+        let mut data = vec![0x00, 0x43];
+        "creative_trash".to_string().serialize(&mut data).unwrap();
+        data.push(0);
+
+        let packet = decode(&data);
+        let ClientBound::DetachedInventory(ref detached) = packet else {
+            panic!("unexpected packet {packet:?}");
+        };
+        assert!(detached.update.is_none());
+
+        packet.apply(&mut world);
+        assert!(world.detached_inventories.is_empty());
+    }
+
+    fn set_properties(properties: &ObjectProperties) -> Vec<u8> {
+        let mut data = vec![0];
+        properties.serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn object_properties_reach_the_world() {
+        let properties = ObjectProperties {
+            hp_max: 20,
+            visual: Visual::Mesh,
+            mesh: "character.b3d".to_string(),
+            ..ObjectProperties::default()
+        };
+
+        let mut init = vec![1];
+        "__builtin:item".to_string().serialize(&mut init).unwrap();
+        init.push(0);
+        7u16.serialize(&mut init).unwrap();
+        Vec3::new(10.0, 20.0, 30.0).serialize(&mut init).unwrap();
+        Vec3::ZERO.serialize(&mut init).unwrap();
+        20u16.serialize(&mut init).unwrap();
+        init.push(1);
+        RawBytes32(set_properties(&properties)).serialize(&mut init).unwrap();
+
+        // Removes object 3, adds object 7
+        let mut data = vec![0x00, 0x31, 0x00, 0x01, 0x00, 0x03, 0x00, 0x01, 0x00, 0x07, 101];
+        RawBytes32(init).serialize(&mut data).unwrap();
+
+        let packet = decode(&data);
+        let ClientBound::ActiveObjectRemoveAdd(ref remove_add) = packet else {
+            panic!("unexpected packet {packet:?}");
+        };
+        assert_eq!(remove_add.removed, [3]);
+        assert_eq!(remove_add.added.len(), 1);
+
+        let mut world = WorldState::new();
+        packet.apply(&mut world);
+        let object = &world.objects[&7];
+        assert_eq!(object.name, "__builtin:item");
+        assert_eq!(object.position, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(object.properties.visual, Visual::Mesh);
+        assert_eq!(object.properties.mesh, "character.b3d");
+
+        let properties = ObjectProperties {
+            visual: Visual::Cube,
+            ..properties
+        };
+        let mut data = vec![0x00, 0x32, 0x00, 0x07];
+        RawBytes16(set_properties(&properties)).serialize(&mut data).unwrap();
+
+        let packet = decode(&data);
+        let ClientBound::ActiveObjectMessages(ref messages) = packet else {
+            panic!("unexpected packet {packet:?}");
+        };
+        assert_eq!(messages.messages.len(), 1);
+        assert_eq!(messages.messages[0].id, 7);
+
+        packet.apply(&mut world);
+        assert_eq!(world.objects[&7].properties.visual, Visual::Cube);
+    }
+
+    #[test]
+    fn show_formspec() {
+        let formspec = "formspec_version[4]size[8,4]button_exit[3,3;2,1;ok;OK]";
+
+        let mut data = vec![0x00, 0x44];
+        (formspec.len() as u32).serialize(&mut data).unwrap();
+        data.extend(formspec.as_bytes());
+        "mymod:dialog".to_string().serialize(&mut data).unwrap();
+
+        let packet = decode(&data);
+        let ClientBound::ShowFormspec(ref show) = packet else {
+            panic!("unexpected packet {packet:?}");
+        };
+        assert_eq!(show.formspec, formspec);
+        assert_eq!(show.form_name, "mymod:dialog");
+    }
+}
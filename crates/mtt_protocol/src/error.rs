@@ -0,0 +1,60 @@
+use std::io;
+use std::net::SocketAddr;
+
+use crate::ConnectionState;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Datagram doesn't belong to the protocol at all.
+    #[error("invalid protocol ID: {0:#010x}")]
+    InvalidProtocolId(u32),
+    #[error("malformed frame at byte {offset}: {error}")]
+    MalformedFrame {
+        offset: u64,
+        error: mtt_serialize::Error,
+    },
+    #[error("invalid channel: {0}")]
+    InvalidChannel(u8),
+    #[error("split chunk {chunk_number} of packet {seqnum} out of range (chunk count {chunk_count})")]
+    InvalidSplitChunk {
+        seqnum: u16,
+        chunk_number: u16,
+        chunk_count: u16,
+    },
+    #[error("chunk count mismatch for split packet {seqnum} (expected {expected}, got {actual})")]
+    SplitChunkCountMismatch {
+        seqnum: u16,
+        expected: u16,
+        actual: u16,
+    },
+    #[error("{what} exceeds the limit ({len} > {limit})")]
+    LimitExceeded {
+        what: &'static str,
+        len: usize,
+        limit: usize,
+    },
+    #[error("packet is too large to send: {0} bytes")]
+    PacketTooLarge(usize),
+    #[error("unknown peer: {0}")]
+    UnknownPeer(u16),
+    #[error("unexpected peer {peer_id} from {address}")]
+    UnexpectedPeer {
+        peer_id: u16,
+        address: SocketAddr,
+    },
+    #[error("no free peer IDs left")]
+    PeerIdsExhausted,
+    #[error("connection is already in {0:?} state")]
+    AlreadyConnected(ConnectionState),
+    #[error("unsupported capture format")]
+    UnsupportedCapture,
+    #[error("unsupported capture link type: {0}")]
+    UnsupportedLinkType(u32),
+    /// Packet couldn't be encoded.
+    #[error(transparent)]
+    Serialize(#[from] mtt_serialize::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -1,10 +1,19 @@
-use anyhow::{bail, ensure, Result};
-use mtt_serialize::Serialize;
-use std::io::{Read, Write};
+use crate::Error;
+use mtt_serialize::{Result, Serialize};
+use std::io::{Cursor, Read, Write};
 
 const PROTOCOL_ID: u32 = 0x4F457403;
 
-#[derive(Debug)]
+/// Maximum size of a single datagram, including headers.
+pub const MAX_PACKET_SIZE: usize = 512;
+
+/// Protocol ID, peer ID and channel.
+pub const BASE_HEADER_SIZE: usize = 7;
+pub const RELIABLE_HEADER_SIZE: usize = 3;
+pub const ORIGINAL_HEADER_SIZE: usize = 1;
+pub const SPLIT_HEADER_SIZE: usize = 7;
+
+#[derive(Debug, Clone)]
 pub enum Control {
     Ack {
         seqnum: u16,
@@ -46,12 +55,17 @@ impl Serialize for Control {
             },
             2 => Control::Ping,
             3 => Control::Disco,
-            _ => bail!("unknown control type: {}", ty),
+            _ => {
+                return Err(mtt_serialize::Error::UnknownVariant {
+                    what: "control type",
+                    value: ty as u64,
+                })
+            }
         })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Split {
     pub seqnum: u16,
     pub chunk_count: u16,
@@ -74,7 +88,7 @@ impl Serialize for Split {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FrameType {
     Control(Control),
     Original,
@@ -127,11 +141,13 @@ impl Serialize for FrameHeader {
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let protocol_id = u32::deserialize(r)?;
 
-        ensure!(
-            protocol_id == PROTOCOL_ID,
-            "protocol ID mismatch (got {0:08X})",
-            protocol_id
-        );
+        if protocol_id != PROTOCOL_ID {
+            return Err(mtt_serialize::Error::Unexpected {
+                what: "protocol ID",
+                expected: PROTOCOL_ID as u64,
+                actual: protocol_id as u64,
+            });
+        }
 
         let peer_id = u16::deserialize(r)?;
         let channel = u8::deserialize(r)?;
@@ -149,7 +165,12 @@ impl Serialize for FrameHeader {
             0 => FrameType::Control(Control::deserialize(r)?),
             1 => FrameType::Original,
             2 => FrameType::Split(Split::deserialize(r)?),
-            _ => bail!("unknown frame type: {}", ty),
+            _ => {
+                return Err(mtt_serialize::Error::UnknownVariant {
+                    what: "frame type",
+                    value: ty as u64,
+                })
+            }
         };
 
         Ok(Self {
@@ -160,3 +181,26 @@ impl Serialize for FrameHeader {
         })
     }
 }
+
+impl FrameHeader {
+    /// Decodes the header at the start of a datagram. Returns the header and
+    /// offset of the payload.
+    pub fn decode(data: &[u8]) -> crate::Result<(Self, usize)> {
+        // Checked separately, since datagrams of other protocols aren't
+        // malformed frames
+        if let Some(protocol_id) = data.get(..4) {
+            let protocol_id = u32::from_be_bytes(protocol_id.try_into().unwrap());
+            if protocol_id != PROTOCOL_ID {
+                return Err(Error::InvalidProtocolId(protocol_id));
+            }
+        }
+
+        let r = &mut Cursor::new(data);
+        let header = Self::deserialize(r).map_err(|error| Error::MalformedFrame {
+            offset: r.position(),
+            error,
+        })?;
+
+        Ok((header, r.position() as usize))
+    }
+}
@@ -1,53 +1,146 @@
 use std::collections::VecDeque;
-use std::io::{Cursor, Write};
+use std::io::Cursor;
+use std::time::Instant;
 
+use crate::capture::{Direction, Recorder};
 use crate::clientbound::ClientBound;
-use crate::frame::{Control, FrameHeader, FrameType, Reliability};
+use crate::peer::{Peer, PeerEvent};
 use crate::serverbound::ServerBound;
-use anyhow::Result;
-use mtt_serialize::Serialize;
+use crate::session::Session;
+use mtt_serialize::{Serialize, Versions};
 
+#[cfg(feature = "tokio")]
+pub mod async_client;
+pub mod auth;
+pub mod capture;
+mod channel;
 pub mod clientbound;
+mod error;
 pub mod frame;
+mod peer;
+mod reliable;
+pub mod replay;
+pub mod server;
 pub mod serverbound;
+pub mod session;
+pub mod sim;
+mod split;
+mod stats;
+
+pub use crate::error::{Error, Result};
+pub use crate::server::Server;
+pub use crate::session::{ClientConfig, ConnectionState, DisconnectReason, SessionError};
+pub use crate::stats::Stats;
 
 pub enum Input<'a> {
+    /// Starts the login sequence, or starts over once disconnected.
+    Connect,
+    /// Notifies the server and closes the connection.
+    Disconnect,
+    /// Creates the account if the password matches the configured one. Only
+    /// has an effect in [`ConnectionState::Register`].
+    ConfirmRegistration(&'a str),
     Receive(&'a [u8]),
     Packet {
         is_reliable: bool,
         packet: ServerBound,
     },
+    Tick(Instant),
     None,
 }
 
 pub enum Output {
     Packet(ClientBound),
     Send(Vec<u8>),
+    InvalidPacket(InvalidPacket),
+    StateChanged(ConnectionState),
+    Disconnected(DisconnectReason),
+    /// Password given to confirm registration didn't match. Registration
+    /// can be confirmed again.
+    PasswordMismatch,
     None,
 }
 
-pub enum ConnectionState {
-    Start,
-    Handshake,
-    InGame,
+/// Packet which was received in full, but couldn't be decoded.
+#[derive(Debug)]
+pub struct InvalidPacket {
+    pub id: Option<u16>,
+    /// Position in `data` where decoding failed.
+    pub offset: u64,
+    pub data: Vec<u8>,
+    pub error: mtt_serialize::Error,
 }
 
 pub struct Client {
-    state: ConnectionState,
+    session: Session,
     output_queue: VecDeque<Output>,
 
-    peer_id: u16,
-    seqnum: u16,
+    peer: Peer,
+    now: Instant,
+
+    recorder: Option<Box<dyn Recorder>>,
+}
+
+/// Reads packet ID of a payload which failed to decode.
+pub(crate) fn peek_packet_id(data: &[u8]) -> Option<u16> {
+    data.get(..2).map(|id| u16::from_be_bytes([id[0], id[1]]))
 }
 
 impl Client {
-    pub fn new() -> Self {
+    pub fn new(config: ClientConfig) -> Self {
+        Self::new_at(config, Instant::now())
+    }
+
+    /// Creates a client whose clock starts at `now`, for callers which keep
+    /// their own time, e.g. simulations.
+    pub fn new_at(config: ClientConfig, now: Instant) -> Self {
         Self {
-            state: ConnectionState::Start,
+            session: Session::new(config, now),
             output_queue: VecDeque::new(),
 
-            peer_id: 0,
-            seqnum: 0xF1FE,
+            peer: Peer::new(0, now),
+            now,
+
+            recorder: None,
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.session.state()
+    }
+
+    /// Protocol and serialization versions negotiated with the server, once
+    /// known.
+    pub fn versions(&self) -> Option<Versions> {
+        self.session.versions()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.peer.stats()
+    }
+
+    /// Time by which `Input::Tick` has to be handled for resends, keepalive
+    /// and timeouts to happen on time. `None` if there is nothing to wait
+    /// for, e.g. before connecting or after disconnecting.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match self.state() {
+            ConnectionState::Start | ConnectionState::Disconnected => None,
+            _ => {
+                let peer = self.peer.next_deadline(self.session.config().timeout);
+                Some(self.session_deadline().map_or(peer, |session| session.min(peer)))
+            }
+        }
+    }
+
+    /// Sets a recorder which gets a copy of every sent and received datagram.
+    pub fn set_recorder(&mut self, recorder: Option<Box<dyn Recorder>>) {
+        self.recorder = recorder;
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) -> Result<()> {
+        match &mut self.recorder {
+            Some(recorder) => recorder.record(direction, data, self.now),
+            None => Ok(()),
         }
     }
 
@@ -55,90 +148,125 @@ impl Client {
         self.output_queue.drain(..)
     }
 
-    fn send_frame(&mut self, header: FrameHeader, data: &[u8]) -> Result<()> {
-        let mut buf = Vec::new();
-
-        header.serialize(&mut buf)?;
+    fn handle_payload(&mut self, data: Vec<u8>) -> Result<()> {
+        let r = &mut Cursor::new(data.as_slice());
+        // Until `Hello` arrives, nothing depends on the versions
+        let versions = self.session.versions().unwrap_or(Versions::LATEST);
+        let result = mtt_serialize::with_limits(self.session.config().limits, || {
+            mtt_serialize::with_versions(versions, || ClientBound::deserialize(r))
+        });
+        let offset = r.position();
 
-        buf.write_all(data)?;
+        let output = match result {
+            Ok(packet) => {
+                self.session_handle_packet(&packet)?;
+                Output::Packet(packet)
+            }
+            Err(error) => Output::InvalidPacket(InvalidPacket {
+                id: peek_packet_id(&data),
+                offset,
+                data,
+                error,
+            }),
+        };
 
-        self.output_queue.push_back(Output::Send(buf));
+        self.output_queue.push_back(output);
 
         Ok(())
     }
 
-    fn send_ack(&mut self, channel: u8, seqnum: u16) -> Result<()> {
-        let header = FrameHeader {
-            peer_id: self.peer_id,
-            channel,
-            reliability: Reliability::Unreliable,
-            ty: FrameType::Control(Control::Ack { seqnum }),
-        };
+    fn handle_clientbound_data(&mut self, data: &[u8]) -> Result<()> {
+        // Datagrams are processed even if recording fails, so the connection isn't affected
+        let recorded = self.record(Direction::Incoming, data);
 
-        self.send_frame(header, &[])
+        self.process_clientbound_data(data).and(recorded)
     }
 
-    fn handle_control(&mut self, control: Control) {
-        match control {
-            Control::Ack { seqnum } => {}
-            Control::SetPeerId { peer_id } => {
-                self.peer_id = peer_id;
-            }
-            Control::Ping => {}
-            Control::Disco => {}
+    fn process_clientbound_data(&mut self, data: &[u8]) -> Result<()> {
+        let limits = self.session.config().limits;
+        let received = mtt_serialize::with_limits(limits, || self.peer.receive(data, self.now));
+
+        // Every event is handled, and the first error is reported afterwards
+        let mut result = received;
+        let events: Vec<_> = self.peer.poll_events().collect();
+        for event in events {
+            let handled = match event {
+                PeerEvent::Payload(payload) => self.handle_payload(payload),
+                PeerEvent::SetPeerId(peer_id) => {
+                    self.peer.peer_id = peer_id;
+                    self.session_peer_id_assigned()
+                }
+                PeerEvent::Disco => {
+                    if self.state() != ConnectionState::Disconnected {
+                        self.disconnect(DisconnectReason::Closed);
+                    }
+                    Ok(())
+                }
+            };
+            result = result.and(handled);
         }
+
+        result
     }
 
-    fn handle_clientbound_data(&mut self, data: &[u8]) -> Result<()> {
-        let r = &mut Cursor::new(data);
+    fn send_packet(&mut self, is_reliable: bool, packet: ServerBound) -> Result<()> {
+        let mut data = Vec::new();
+
+        // Until `Hello` arrives, nothing depends on the versions
+        let versions = self.session.versions().unwrap_or(Versions::LATEST);
+        mtt_serialize::with_versions(versions, || packet.serialize(&mut data))?;
 
-        let frame_header = FrameHeader::deserialize(r)?;
+        self.peer.send_payload(packet.channel(), is_reliable, data, self.now)
+    }
+
+    fn handle_tick(&mut self, now: Instant) -> Result<()> {
+        self.now = now;
 
-        if let Reliability::Reliable { seqnum } = frame_header.reliability {
-            self.send_ack(frame_header.channel, seqnum)?;
+        // Nobody is listening on the other side anymore
+        if self.state() == ConnectionState::Disconnected {
+            return Ok(());
         }
 
-        println!("{:?}", frame_header);
+        self.peer.tick(now)?;
+        self.session_tick()?;
 
-        match frame_header.ty {
-            FrameType::Control(control) => self.handle_control(control),
-            _ => {}
+        if self.state() != ConnectionState::Start && self.peer.keepalive(now, self.session.config().timeout)? {
+            self.disconnect(DisconnectReason::Timeout);
         }
 
         Ok(())
-        // match self.state {
-        //     ConnectionState::Start => todo!(),
-        //     ConnectionState::Handshake => todo!(),
-        //     ConnectionState::InGame => todo!(),
-        // }
-    }
-
-    fn handle_serverbound_packet(&mut self, is_reliable: bool, packet: ServerBound) -> Result<()> {
-        let reliability = if is_reliable {
-            Reliability::Reliable { seqnum: self.seqnum }
-        } else {
-            Reliability::Unreliable
-        };
-
-        let frame = FrameHeader {
-            peer_id: self.peer_id,
-            channel: 0,
-            reliability,
-            ty: FrameType::Original,
-        };
+    }
 
-        let mut data = Vec::new();
+    fn handle_disconnect(&mut self) -> Result<()> {
+        if self.state() == ConnectionState::Disconnected {
+            return Ok(());
+        }
 
-        packet.serialize(&mut data)?;
+        self.peer.send_disco(self.now)?;
+        self.disconnect(DisconnectReason::Local);
 
-        self.send_frame(frame, &data)
+        Ok(())
     }
 
     pub fn handle_input(&mut self, input: Input) -> Result<()> {
-        match input {
+        let result = match input {
+            Input::Connect => self.session_connect(),
+            Input::Disconnect => self.handle_disconnect(),
+            Input::ConfirmRegistration(password) => self.session_confirm_registration(password),
             Input::Receive(data) => self.handle_clientbound_data(data),
-            Input::Packet { is_reliable, packet } => self.handle_serverbound_packet(is_reliable, packet),
+            Input::Packet { is_reliable, packet } => self.send_packet(is_reliable, packet),
+            Input::Tick(now) => self.handle_tick(now),
             Input::None => Ok(()),
-        }
+        };
+
+        let datagrams: Vec<_> = self.peer.poll_datagrams().collect();
+
+        // Datagrams are sent even if recording fails, so the connection isn't affected
+        let recorded = datagrams
+            .iter()
+            .try_for_each(|data| self.record(Direction::Outgoing, data));
+        self.output_queue.extend(datagrams.into_iter().map(Output::Send));
+
+        result.and(recorded)
     }
 }
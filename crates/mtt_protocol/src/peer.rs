@@ -0,0 +1,331 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::channel::{Channel, CHANNEL_COUNT};
+use crate::frame::{
+    Control, FrameHeader, FrameType, Reliability, BASE_HEADER_SIZE, MAX_PACKET_SIZE, ORIGINAL_HEADER_SIZE,
+    RELIABLE_HEADER_SIZE,
+};
+use crate::reliable::Received;
+use crate::split::{split_payload, SPLIT_TIMEOUT};
+use crate::stats::{Stats, StatsTracker};
+use crate::{Error, Result};
+use mtt_serialize::Serialize;
+
+/// Ping is sent if nothing else was sent for this long.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Something the remote side has sent, after reliability and split frames
+/// were taken care of.
+#[derive(Debug)]
+pub enum PeerEvent {
+    /// Complete packet payload.
+    Payload(Vec<u8>),
+    SetPeerId(u16),
+    Disco,
+}
+
+fn encode_frame(header: &FrameHeader, data: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+
+    header.serialize(&mut buf)?;
+
+    buf.write_all(data)?;
+
+    Ok(buf)
+}
+
+/// Transport state of one side of a connection: reliable channels, split
+/// reassembly, keepalive and connection statistics.
+pub struct Peer {
+    /// Peer ID written into outgoing frames.
+    pub peer_id: u16,
+    channels: [Channel; CHANNEL_COUNT],
+    datagrams: VecDeque<Vec<u8>>,
+    events: VecDeque<PeerEvent>,
+    stats: StatsTracker,
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+impl Peer {
+    pub fn new(peer_id: u16, now: Instant) -> Self {
+        Self {
+            peer_id,
+            channels: std::array::from_fn(|_| Channel::new()),
+            datagrams: VecDeque::new(),
+            events: VecDeque::new(),
+            stats: StatsTracker::new(),
+            last_received: now,
+            last_sent: now,
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let in_flight = self.channels.iter().map(|channel| channel.outgoing.in_flight()).sum();
+        self.stats.snapshot(in_flight)
+    }
+
+    /// Returns datagrams which have to be sent to the remote side.
+    pub fn poll_datagrams(&mut self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.datagrams.drain(..)
+    }
+
+    /// Returns what the remote side has sent. Events are kept even if
+    /// `receive` fails on a later frame of the same datagram.
+    pub fn poll_events(&mut self) -> impl Iterator<Item = PeerEvent> + '_ {
+        self.events.drain(..)
+    }
+
+    fn send_frame(&mut self, header: FrameHeader, data: &[u8], now: Instant) -> Result<()> {
+        let buf = encode_frame(&header, data)?;
+
+        self.datagrams.push_back(buf);
+        self.last_sent = now;
+
+        Ok(())
+    }
+
+    pub fn send_reliable(&mut self, channel: u8, ty: FrameType, data: Vec<u8>, now: Instant) -> Result<()> {
+        self.channels[channel as usize].outgoing.push(ty, data);
+        self.flush_reliable(now)
+    }
+
+    fn send_split(&mut self, channel: u8, data: &[u8], now: Instant) -> Result<()> {
+        let channel = &mut self.channels[channel as usize];
+        let seqnum = channel.next_split_seqnum();
+
+        for (split, chunk) in split_payload(seqnum, data)? {
+            channel.outgoing.push(FrameType::Split(split), chunk.to_vec());
+        }
+
+        self.flush_reliable(now)
+    }
+
+    pub fn send_payload(&mut self, channel: u8, is_reliable: bool, data: Vec<u8>, now: Instant) -> Result<()> {
+        // Packets that don't fit into a single datagram are always sent
+        // as reliable split frames
+        let max_size = MAX_PACKET_SIZE - BASE_HEADER_SIZE - RELIABLE_HEADER_SIZE - ORIGINAL_HEADER_SIZE;
+        if data.len() > max_size {
+            return self.send_split(channel, &data, now);
+        }
+
+        if is_reliable {
+            return self.send_reliable(channel, FrameType::Original, data, now);
+        }
+
+        let frame = FrameHeader {
+            peer_id: self.peer_id,
+            channel,
+            reliability: Reliability::Unreliable,
+            ty: FrameType::Original,
+        };
+
+        self.send_frame(frame, &data, now)
+    }
+
+    /// Transmits new reliable frames that fit into the window and resends
+    /// the ones that weren't acknowledged in time.
+    fn flush_reliable(&mut self, now: Instant) -> Result<()> {
+        let resend_timeout = self.stats.resend_timeout();
+
+        for (channel_id, channel) in self.channels.iter_mut().enumerate() {
+            for frame in channel.outgoing.poll_transmit(now, resend_timeout) {
+                self.stats.report_transmission(frame.resend_count > 0);
+
+                let header = FrameHeader {
+                    peer_id: self.peer_id,
+                    channel: channel_id as u8,
+                    reliability: Reliability::Reliable { seqnum: frame.seqnum },
+                    ty: frame.ty.clone(),
+                };
+
+                let buf = encode_frame(&header, &frame.data)?;
+                self.datagrams.push_back(buf);
+                self.last_sent = now;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send_ack(&mut self, channel: u8, seqnum: u16, now: Instant) -> Result<()> {
+        let header = FrameHeader {
+            peer_id: self.peer_id,
+            channel,
+            reliability: Reliability::Unreliable,
+            ty: FrameType::Control(Control::Ack { seqnum }),
+        };
+
+        self.send_frame(header, &[], now)
+    }
+
+    /// Notifies the remote side that the connection is closed.
+    pub fn send_disco(&mut self, now: Instant) -> Result<()> {
+        let header = FrameHeader {
+            peer_id: self.peer_id,
+            channel: 0,
+            reliability: Reliability::Unreliable,
+            ty: FrameType::Control(Control::Disco),
+        };
+
+        self.send_frame(header, &[], now)
+    }
+
+    fn handle_frame(&mut self, channel: u8, ty: FrameType, payload: Vec<u8>, now: Instant) -> Result<()> {
+        let event = match ty {
+            FrameType::Control(Control::Ack { seqnum }) => {
+                if let Some(rtt) = self.channels[channel as usize].outgoing.ack(seqnum, now) {
+                    self.stats.report_rtt(rtt);
+                }
+
+                None
+            }
+            FrameType::Control(Control::SetPeerId { peer_id }) => Some(PeerEvent::SetPeerId(peer_id)),
+            FrameType::Control(Control::Ping) => None,
+            FrameType::Control(Control::Disco) => Some(PeerEvent::Disco),
+            FrameType::Original => Some(PeerEvent::Payload(payload)),
+            FrameType::Split(split) => {
+                let splits = &mut self.channels[channel as usize].splits;
+                splits.insert(&split, &payload, now)?.map(PeerEvent::Payload)
+            }
+        };
+
+        self.events.extend(event);
+
+        Ok(())
+    }
+
+    /// Processes a datagram received from the remote side. Resulting events
+    /// are returned by `poll_events`.
+    pub fn receive(&mut self, data: &[u8], now: Instant) -> Result<()> {
+        let (frame_header, header_size) = FrameHeader::decode(data)?;
+
+        if frame_header.channel as usize >= CHANNEL_COUNT {
+            return Err(Error::InvalidChannel(frame_header.channel));
+        }
+
+        self.last_received = now;
+
+        let channel = frame_header.channel;
+        let payload = data[header_size..].to_vec();
+
+        let result = match frame_header.reliability {
+            Reliability::Reliable { seqnum } => {
+                let incoming = &mut self.channels[channel as usize].incoming;
+
+                match incoming.insert(seqnum, frame_header.ty, payload) {
+                    Received::Accepted | Received::Duplicate => self.send_ack(channel, seqnum, now)?,
+                    Received::OutOfWindow => {}
+                }
+
+                // Frames were acked already, so the ones after a failing
+                // frame are still delivered
+                let mut result = Ok(());
+                while let Some((ty, payload)) = self.channels[channel as usize].incoming.pop() {
+                    let handled = self.handle_frame(channel, ty, payload, now);
+                    result = result.and(handled);
+                }
+
+                result
+            }
+            Reliability::Unreliable => self.handle_frame(channel, frame_header.ty, payload, now),
+        };
+
+        // Acks may have freed some space in the window
+        let flushed = self.flush_reliable(now);
+
+        result.and(flushed)
+    }
+
+    pub fn tick(&mut self, now: Instant) -> Result<()> {
+        for channel in &mut self.channels {
+            channel.splits.expire(now, SPLIT_TIMEOUT);
+        }
+
+        self.flush_reliable(now)
+    }
+
+    /// Earliest time at which `tick` or `keepalive` have something to do:
+    /// a resend, split expiry, ping or timeout.
+    pub fn next_deadline(&self, timeout: Duration) -> Instant {
+        let resend_timeout = self.stats.resend_timeout();
+        let keepalive = (self.last_sent + PING_INTERVAL).min(self.last_received + timeout);
+
+        self.channels
+            .iter()
+            .flat_map(|channel| {
+                let resend = channel.outgoing.next_resend(resend_timeout);
+                let expiry = channel.splits.next_expiry(SPLIT_TIMEOUT);
+                resend.into_iter().chain(expiry)
+            })
+            .fold(keepalive, Instant::min)
+    }
+
+    /// Pings the remote side if the connection is idle. Returns `true` if
+    /// nothing was received for `timeout`.
+    pub fn keepalive(&mut self, now: Instant, timeout: Duration) -> Result<bool> {
+        if now.duration_since(self.last_received) >= timeout {
+            return Ok(true);
+        }
+
+        if now.duration_since(self.last_sent) >= PING_INTERVAL {
+            self.send_reliable(0, FrameType::Control(Control::Ping), Vec::new(), now)?;
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::SEQNUM_INITIAL;
+    use crate::frame::Split;
+
+    fn reliable_frame(seqnum: u16, ty: FrameType, data: &[u8]) -> Vec<u8> {
+        let header = FrameHeader {
+            peer_id: 1,
+            channel: 0,
+            reliability: Reliability::Reliable { seqnum },
+            ty,
+        };
+
+        encode_frame(&header, data).unwrap()
+    }
+
+    #[test]
+    fn frames_before_a_failing_one_are_delivered() {
+        let now = Instant::now();
+        let mut peer = Peer::new(2, now);
+
+        // Claims more chunks than allowed, and waits for the frame before it
+        let split = Split {
+            seqnum: 0,
+            chunk_count: u16::MAX,
+            chunk_number: 0,
+        };
+        let data = reliable_frame(SEQNUM_INITIAL.wrapping_add(1), FrameType::Split(split), &[0; 10]);
+        peer.receive(&data, now).unwrap();
+        assert_eq!(peer.poll_events().count(), 0);
+
+        let data = reliable_frame(SEQNUM_INITIAL, FrameType::Original, &[1, 2, 3]);
+        let error = peer.receive(&data, now).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::LimitExceeded {
+                what: "split chunk count",
+                ..
+            }
+        ));
+
+        let events: Vec<_> = peer.poll_events().collect();
+        assert!(matches!(events.as_slice(), [PeerEvent::Payload(payload)] if payload == &[1, 2, 3]));
+
+        // Both frames were acked, so the payload isn't delivered again
+        assert_eq!(peer.poll_datagrams().count(), 2);
+        peer.receive(&data, now).unwrap();
+        assert_eq!(peer.poll_events().count(), 0);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::frame::FrameType;
+
+/// Maximum number of unacknowledged reliable frames per channel.
+///
+/// Must stay below half of the seqnum space, otherwise seqnums of in-flight
+/// frames would become ambiguous after wraparound.
+pub const WINDOW_SIZE: u16 = 0x40;
+
+/// How far ahead of the next expected seqnum incoming frames are buffered.
+pub const RECEIVE_WINDOW_SIZE: u16 = 0x4000;
+
+/// Returns `true` if `a` comes after `b`, taking u16 wraparound into account.
+pub fn seqnum_higher(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+#[derive(Debug)]
+pub struct ReliableFrame {
+    pub seqnum: u16,
+    pub ty: FrameType,
+    pub data: Vec<u8>,
+    pub resend_count: u32,
+    sent_at: Option<Instant>,
+}
+
+/// Sending half of a reliable channel.
+///
+/// Frames are assigned consecutive seqnums and kept until the peer
+/// acknowledges them. Frames that don't fit into the window wait in a queue.
+pub struct ReliableSender {
+    next_seqnum: u16,
+    in_flight: VecDeque<ReliableFrame>,
+    queue: VecDeque<ReliableFrame>,
+}
+
+impl ReliableSender {
+    pub fn new(initial_seqnum: u16) -> Self {
+        Self {
+            next_seqnum: initial_seqnum,
+            in_flight: VecDeque::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, ty: FrameType, data: Vec<u8>) -> u16 {
+        let seqnum = self.next_seqnum;
+        self.next_seqnum = self.next_seqnum.wrapping_add(1);
+
+        self.queue.push_back(ReliableFrame {
+            seqnum,
+            ty,
+            data,
+            resend_count: 0,
+            sent_at: None,
+        });
+
+        seqnum
+    }
+
+    /// Removes acknowledged frame from the window and returns its round-trip
+    /// time. Resent frames don't produce an RTT sample, since it's unknown
+    /// which transmission the ack belongs to.
+    pub fn ack(&mut self, seqnum: u16, now: Instant) -> Option<Duration> {
+        let index = self.in_flight.iter().position(|frame| frame.seqnum == seqnum)?;
+        let frame = self.in_flight.remove(index)?;
+
+        match (frame.resend_count, frame.sent_at) {
+            (0, Some(sent_at)) => Some(now.duration_since(sent_at)),
+            _ => None,
+        }
+    }
+
+    /// Returns frames which have to be (re)transmitted now: queued frames
+    /// that fit into the window and in-flight frames older than `timeout`.
+    pub fn poll_transmit(&mut self, now: Instant, timeout: Duration) -> Vec<&ReliableFrame> {
+        while self.in_flight.len() < WINDOW_SIZE as usize {
+            match self.queue.pop_front() {
+                Some(frame) => self.in_flight.push_back(frame),
+                None => break,
+            }
+        }
+
+        let mut frames = Vec::new();
+
+        for frame in &mut self.in_flight {
+            match frame.sent_at {
+                None => {
+                    frame.sent_at = Some(now);
+                }
+                Some(sent_at) if now.duration_since(sent_at) >= timeout => {
+                    frame.sent_at = Some(now);
+                    frame.resend_count += 1;
+                }
+                Some(_) => continue,
+            }
+
+            frames.push(&*frame);
+        }
+
+        frames
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// When the earliest in-flight frame is due to be resent.
+    pub fn next_resend(&self, timeout: Duration) -> Option<Instant> {
+        self.in_flight
+            .iter()
+            .filter_map(|frame| frame.sent_at)
+            .min()
+            .map(|sent_at| sent_at + timeout)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// Frame was buffered and will be delivered in order.
+    Accepted,
+    /// Frame was already received before. It still has to be acked, since
+    /// the previous ack might have been lost.
+    Duplicate,
+    /// Frame is too far ahead of the window and was dropped.
+    OutOfWindow,
+}
+
+/// Receiving half of a reliable channel.
+///
+/// Buffers frames that arrive early and hands them out strictly in seqnum
+/// order, dropping the ones which were already delivered.
+pub struct ReliableReceiver {
+    next_seqnum: u16,
+    buffer: HashMap<u16, (FrameType, Vec<u8>)>,
+}
+
+impl ReliableReceiver {
+    pub fn new(initial_seqnum: u16) -> Self {
+        Self {
+            next_seqnum: initial_seqnum,
+            buffer: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, seqnum: u16, ty: FrameType, data: Vec<u8>) -> Received {
+        if seqnum_higher(self.next_seqnum, seqnum) || self.buffer.contains_key(&seqnum) {
+            return Received::Duplicate;
+        }
+
+        if seqnum.wrapping_sub(self.next_seqnum) >= RECEIVE_WINDOW_SIZE {
+            return Received::OutOfWindow;
+        }
+
+        self.buffer.insert(seqnum, (ty, data));
+
+        Received::Accepted
+    }
+
+    /// Returns the next frame in order, if it has arrived.
+    pub fn pop(&mut self) -> Option<(FrameType, Vec<u8>)> {
+        let frame = self.buffer.remove(&self.next_seqnum)?;
+        self.next_seqnum = self.next_seqnum.wrapping_add(1);
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    fn seqnums(frames: &[&ReliableFrame]) -> Vec<u16> {
+        frames.iter().map(|frame| frame.seqnum).collect()
+    }
+
+    #[test]
+    fn seqnum_higher_wraps_around() {
+        assert!(seqnum_higher(1, 0));
+        assert!(!seqnum_higher(0, 1));
+        assert!(!seqnum_higher(7, 7));
+        assert!(seqnum_higher(0, 65535));
+        assert!(seqnum_higher(5, 65530));
+        assert!(!seqnum_higher(65535, 0));
+        assert!(!seqnum_higher(65530, 5));
+    }
+
+    #[test]
+    fn frames_are_resent_after_timeout() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new(65500);
+        sender.push(FrameType::Original, vec![1]);
+        sender.push(FrameType::Original, vec![2]);
+
+        assert_eq!(seqnums(&sender.poll_transmit(start, TIMEOUT)), [65500, 65501]);
+        assert!(sender.poll_transmit(start + TIMEOUT / 2, TIMEOUT).is_empty());
+
+        let frames = sender.poll_transmit(start + TIMEOUT, TIMEOUT);
+        assert_eq!(seqnums(&frames), [65500, 65501]);
+        assert!(frames.iter().all(|frame| frame.resend_count == 1));
+    }
+
+    #[test]
+    fn acked_frames_are_dropped() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new(65535);
+        sender.push(FrameType::Original, vec![1]);
+        sender.push(FrameType::Original, vec![2]);
+        sender.poll_transmit(start, TIMEOUT);
+
+        let rtt = sender.ack(65535, start + Duration::from_millis(80));
+        assert_eq!(rtt, Some(Duration::from_millis(80)));
+        assert_eq!(sender.ack(65535, start + Duration::from_millis(90)), None);
+        assert_eq!(sender.in_flight(), 1);
+
+        assert_eq!(seqnums(&sender.poll_transmit(start + TIMEOUT, TIMEOUT)), [0]);
+    }
+
+    #[test]
+    fn queued_frames_wait_for_the_window() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new(0);
+        for i in 0..=WINDOW_SIZE {
+            sender.push(FrameType::Original, vec![i as u8]);
+        }
+
+        assert_eq!(sender.poll_transmit(start, TIMEOUT).len(), WINDOW_SIZE as usize);
+        sender.ack(0, start);
+        assert_eq!(seqnums(&sender.poll_transmit(start, TIMEOUT)), [WINDOW_SIZE]);
+    }
+
+    fn pop_data(receiver: &mut ReliableReceiver) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some((_, payload)) = receiver.pop() {
+            data.extend(payload);
+        }
+        data
+    }
+
+    #[test]
+    fn early_frames_are_buffered() {
+        let mut receiver = ReliableReceiver::new(10);
+
+        assert_eq!(receiver.insert(12, FrameType::Original, vec![12]), Received::Accepted);
+        assert_eq!(receiver.insert(11, FrameType::Original, vec![11]), Received::Accepted);
+        assert!(receiver.pop().is_none());
+
+        assert_eq!(receiver.insert(10, FrameType::Original, vec![10]), Received::Accepted);
+        assert_eq!(pop_data(&mut receiver), [10, 11, 12]);
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let mut receiver = ReliableReceiver::new(10);
+
+        assert_eq!(receiver.insert(11, FrameType::Original, vec![11]), Received::Accepted);
+        assert_eq!(receiver.insert(11, FrameType::Original, vec![11]), Received::Duplicate);
+        receiver.insert(10, FrameType::Original, vec![10]);
+        assert_eq!(pop_data(&mut receiver), [10, 11]);
+
+        // Already delivered
+        assert_eq!(receiver.insert(10, FrameType::Original, vec![10]), Received::Duplicate);
+        assert_eq!(receiver.insert(11, FrameType::Original, vec![11]), Received::Duplicate);
+        assert!(receiver.pop().is_none());
+    }
+
+    #[test]
+    fn frames_too_far_ahead_are_dropped() {
+        let mut receiver = ReliableReceiver::new(10);
+
+        let seqnum = 10u16.wrapping_add(RECEIVE_WINDOW_SIZE);
+        assert_eq!(
+            receiver.insert(seqnum, FrameType::Original, vec![]),
+            Received::OutOfWindow
+        );
+    }
+
+    #[test]
+    fn delivery_continues_across_wraparound() {
+        let mut receiver = ReliableReceiver::new(65534);
+
+        for seqnum in [1, 0, 65535, 65534] {
+            assert_eq!(
+                receiver.insert(seqnum, FrameType::Original, vec![seqnum as u8]),
+                Received::Accepted
+            );
+        }
+        assert_eq!(pop_data(&mut receiver), [254, 255, 0, 1]);
+
+        assert_eq!(receiver.insert(65535, FrameType::Original, vec![]), Received::Duplicate);
+        assert_eq!(receiver.insert(2, FrameType::Original, vec![2]), Received::Accepted);
+        assert_eq!(pop_data(&mut receiver), [2]);
+    }
+}
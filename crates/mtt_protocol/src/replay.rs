@@ -0,0 +1,167 @@
+//! Offline playback of captured sessions.
+
+use std::collections::VecDeque;
+use std::io::Read;
+use std::time::Instant;
+
+use crate::capture::{read_pcap, CapturedDatagram, Direction};
+use crate::{Client, ClientConfig, Input, Output, Result};
+use mtt_core::world::WorldState;
+
+/// Feeds datagrams received from the server during a captured session into
+/// a fresh [`Client`], and applies decoded packets to a [`WorldState`].
+///
+/// Datagrams are delivered with their original timing relative to each
+/// other, so playback is deterministic. Whatever the client tries to send
+/// is dropped.
+pub struct Replay {
+    pub client: Client,
+    pub world: WorldState,
+    datagrams: VecDeque<CapturedDatagram>,
+    started_at: Instant,
+    output_queue: VecDeque<Output>,
+}
+
+impl Replay {
+    pub fn new(config: ClientConfig, datagrams: Vec<CapturedDatagram>) -> Result<Self> {
+        let started_at = Instant::now();
+        let mut client = Client::new_at(config, started_at);
+
+        client.handle_input(Input::Connect)?;
+
+        let mut replay = Self {
+            client,
+            world: WorldState::new(),
+            datagrams: datagrams
+                .into_iter()
+                .filter(|datagram| datagram.direction == Direction::Incoming)
+                .collect(),
+            started_at,
+            output_queue: VecDeque::new(),
+        };
+        replay.collect_outputs();
+
+        Ok(replay)
+    }
+
+    pub fn from_pcap<R: Read>(config: ClientConfig, r: R) -> Result<Self> {
+        Self::new(config, read_pcap(r)?)
+    }
+
+    /// Outputs of the client, except for datagrams.
+    pub fn poll_output(&mut self) -> impl Iterator<Item = Output> + '_ {
+        self.output_queue.drain(..)
+    }
+
+    /// Number of datagrams which weren't delivered yet.
+    pub fn remaining(&self) -> usize {
+        self.datagrams.len()
+    }
+
+    fn collect_outputs(&mut self) {
+        for output in self.client.poll_output() {
+            match output {
+                Output::Send(_) => {}
+                Output::Packet(packet) => {
+                    packet.apply(&mut self.world);
+                    self.output_queue.push_back(Output::Packet(packet));
+                }
+                output => self.output_queue.push_back(output),
+            }
+        }
+    }
+
+    /// Delivers the next datagram. Returns `false` once the capture is over.
+    pub fn step(&mut self) -> Result<bool> {
+        let Some(datagram) = self.datagrams.pop_front() else {
+            return Ok(false);
+        };
+
+        let now = self.started_at + datagram.time;
+
+        let result = self
+            .client
+            .handle_input(Input::Tick(now))
+            .and_then(|_| self.client.handle_input(Input::Receive(&datagram.data)));
+
+        // Packets decoded before an error are still applied
+        self.collect_outputs();
+
+        result.map(|_| true)
+    }
+
+    /// Delivers all remaining datagrams, stopping at the first one which
+    /// can't be handled.
+    pub fn run(&mut self) -> Result<()> {
+        while self.step()? {}
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::Recorder;
+    use glam::{I16Vec3, Vec3};
+    use mtt_serialize::Versions;
+
+    /// Login up to `Hello`, followed by a few packets which change the world.
+    const SESSION: &[u8] = include_bytes!("../testdata/session.pcap");
+
+    #[test]
+    fn captured_session_is_applied_to_the_world() {
+        let mut replay = Replay::from_pcap(ClientConfig::new("player"), SESSION).unwrap();
+        replay.run().unwrap();
+
+        assert_eq!(replay.remaining(), 0);
+        assert_eq!(
+            replay.client.versions(),
+            Some(Versions {
+                protocol: 42,
+                serialization: 29,
+            })
+        );
+
+        let world = &replay.world;
+        assert_eq!(world.time, 6000.0);
+        assert_eq!(world.time_speed, 72.0);
+        assert_eq!(world.player.position, Vec3::new(10.0, 20.0, 30.0));
+
+        let block = world.map.get(&I16Vec3::new(1, 2, 3)).unwrap();
+        assert_eq!(block.node(0, 0, 0).id, 5);
+        assert_eq!(block.node(15, 15, 15).id, 0);
+    }
+
+    struct FailingRecorder;
+
+    impl Recorder for FailingRecorder {
+        fn record(&mut self, _: Direction, _: &[u8], _: Instant) -> Result<()> {
+            Err(std::io::Error::other("disk full").into())
+        }
+    }
+
+    #[test]
+    fn failing_recorder_doesnt_stop_the_session() {
+        let started_at = Instant::now();
+        let mut client = Client::new_at(ClientConfig::new("player"), started_at);
+        client.set_recorder(Some(Box::new(FailingRecorder)));
+        assert!(client.handle_input(Input::Connect).is_err());
+
+        let mut packets = 0;
+        for datagram in read_pcap(SESSION).unwrap() {
+            if datagram.direction == Direction::Incoming {
+                let _ = client.handle_input(Input::Tick(started_at + datagram.time));
+                assert!(client.handle_input(Input::Receive(&datagram.data)).is_err());
+            }
+
+            packets += client
+                .poll_output()
+                .filter(|output| matches!(output, Output::Packet(_)))
+                .count();
+        }
+
+        assert_eq!(packets, 4);
+        assert!(client.versions().is_some());
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::clientbound::ClientBound;
+use crate::frame::{Control, FrameHeader, FrameType};
+use crate::peer::{Peer, PeerEvent};
+use crate::serverbound::ServerBound;
+use crate::{peek_packet_id, DisconnectReason, Error, InvalidPacket, Result, Stats};
+use mtt_serialize::{Limits, Serialize, Versions};
+
+/// Peer ID used by the server in outgoing frames.
+pub const PEER_ID_SERVER: u16 = 1;
+
+/// Peer ID used by clients which weren't assigned an ID yet.
+pub const PEER_ID_INEXISTENT: u16 = 0;
+
+const PEER_ID_FIRST: u16 = 2;
+
+/// Peers are dropped if nothing is received from them for this long.
+const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum ServerInput<'a> {
+    Receive {
+        from: SocketAddr,
+        data: &'a [u8],
+    },
+    Packet {
+        peer_id: u16,
+        is_reliable: bool,
+        packet: ClientBound,
+    },
+    /// Closes the connection to the peer.
+    Disconnect {
+        peer_id: u16,
+    },
+    Tick(Instant),
+}
+
+pub enum ServerOutput {
+    Send {
+        to: SocketAddr,
+        data: Vec<u8>,
+    },
+    Connected {
+        peer_id: u16,
+        address: SocketAddr,
+    },
+    Packet {
+        peer_id: u16,
+        packet: ServerBound,
+    },
+    InvalidPacket {
+        peer_id: u16,
+        packet: InvalidPacket,
+    },
+    Disconnected {
+        peer_id: u16,
+        reason: DisconnectReason,
+    },
+}
+
+struct RemotePeer {
+    address: SocketAddr,
+    peer: Peer,
+}
+
+/// Server side of the transport. Accepts any number of clients over a single
+/// socket and tells them apart by their address.
+pub struct Server {
+    peers: HashMap<u16, RemotePeer>,
+    addresses: HashMap<SocketAddr, u16>,
+    next_peer_id: u16,
+    output_queue: VecDeque<ServerOutput>,
+    now: Instant,
+    limits: Limits,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::new_at(Instant::now())
+    }
+
+    /// Creates a server whose clock starts at `now`, for callers which keep
+    /// their own time, e.g. simulations.
+    pub fn new_at(now: Instant) -> Self {
+        Self {
+            peers: HashMap::new(),
+            addresses: HashMap::new(),
+            next_peer_id: PEER_ID_FIRST,
+            output_queue: VecDeque::new(),
+            now,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Sets the limits applied to data received from peers.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (u16, SocketAddr)> + '_ {
+        self.peers.iter().map(|(&peer_id, remote)| (peer_id, remote.address))
+    }
+
+    pub fn stats(&self, peer_id: u16) -> Option<Stats> {
+        self.peers.get(&peer_id).map(|remote| remote.peer.stats())
+    }
+
+    pub fn poll_output(&mut self) -> impl Iterator<Item = ServerOutput> + '_ {
+        self.output_queue.drain(..)
+    }
+
+    fn allocate_peer_id(&mut self) -> Result<u16> {
+        for _ in PEER_ID_FIRST..=u16::MAX {
+            let peer_id = self.next_peer_id;
+            self.next_peer_id = self.next_peer_id.checked_add(1).unwrap_or(PEER_ID_FIRST);
+
+            if !self.peers.contains_key(&peer_id) {
+                return Ok(peer_id);
+            }
+        }
+
+        Err(Error::PeerIdsExhausted)
+    }
+
+    fn accept(&mut self, address: SocketAddr) -> Result<u16> {
+        let peer_id = self.allocate_peer_id()?;
+        let mut peer = Peer::new(PEER_ID_SERVER, self.now);
+
+        let set_peer_id = FrameType::Control(Control::SetPeerId { peer_id });
+        peer.send_reliable(0, set_peer_id, Vec::new(), self.now)?;
+
+        self.peers.insert(peer_id, RemotePeer { address, peer });
+        self.addresses.insert(address, peer_id);
+        self.output_queue
+            .push_back(ServerOutput::Connected { peer_id, address });
+
+        Ok(peer_id)
+    }
+
+    fn remove_peer(&mut self, peer_id: u16, reason: DisconnectReason) {
+        if let Some(remote) = self.peers.remove(&peer_id) {
+            self.addresses.remove(&remote.address);
+            self.output_queue
+                .push_back(ServerOutput::Disconnected { peer_id, reason });
+        }
+    }
+
+    fn handle_payload(&mut self, peer_id: u16, data: Vec<u8>) {
+        let r = &mut Cursor::new(data.as_slice());
+        // Versions aren't tracked per peer, so packets are read as sent by
+        // the latest client
+        let result = mtt_serialize::with_limits(self.limits, || {
+            mtt_serialize::with_versions(Versions::LATEST, || ServerBound::deserialize(r))
+        });
+        let offset = r.position();
+
+        let output = match result {
+            Ok(packet) => ServerOutput::Packet { peer_id, packet },
+            Err(error) => {
+                let packet = InvalidPacket {
+                    id: peek_packet_id(&data),
+                    offset,
+                    data,
+                    error,
+                };
+
+                ServerOutput::InvalidPacket { peer_id, packet }
+            }
+        };
+
+        self.output_queue.push_back(output);
+    }
+
+    fn handle_serverbound_data(&mut self, from: SocketAddr, data: &[u8]) -> Result<()> {
+        let peer_id = match self.addresses.get(&from) {
+            Some(&peer_id) => peer_id,
+            None => {
+                // Don't allocate anything for datagrams that aren't even valid frames
+                let (header, _) = FrameHeader::decode(data)?;
+                if header.peer_id != PEER_ID_INEXISTENT {
+                    return Err(Error::UnexpectedPeer {
+                        peer_id: header.peer_id,
+                        address: from,
+                    });
+                }
+
+                self.accept(from)?
+            }
+        };
+
+        let remote = self.peers.get_mut(&peer_id).unwrap();
+        let now = self.now;
+        let received = mtt_serialize::with_limits(self.limits, || remote.peer.receive(data, now));
+
+        let events: Vec<_> = remote.peer.poll_events().collect();
+        for event in events {
+            match event {
+                PeerEvent::Payload(payload) => self.handle_payload(peer_id, payload),
+                PeerEvent::SetPeerId(_) => {}
+                PeerEvent::Disco => {
+                    // Nothing after it belongs to the peer anymore
+                    self.remove_peer(peer_id, DisconnectReason::Closed);
+                    break;
+                }
+            }
+        }
+
+        received
+    }
+
+    fn send_packet(&mut self, peer_id: u16, is_reliable: bool, packet: ClientBound) -> Result<()> {
+        let remote = match self.peers.get_mut(&peer_id) {
+            Some(remote) => remote,
+            None => return Err(Error::UnknownPeer(peer_id)),
+        };
+
+        let mut data = Vec::new();
+        packet.serialize(&mut data)?;
+
+        remote.peer.send_payload(packet.channel(), is_reliable, data, self.now)
+    }
+
+    fn disconnect(&mut self, peer_id: u16) -> Result<()> {
+        if let Some(remote) = self.peers.get_mut(&peer_id) {
+            remote.peer.send_disco(self.now)?;
+            self.flush_datagrams();
+            self.remove_peer(peer_id, DisconnectReason::Kicked);
+        }
+
+        Ok(())
+    }
+
+    fn handle_tick(&mut self, now: Instant) -> Result<()> {
+        self.now = now;
+
+        let mut dropped = Vec::new();
+
+        // A peer which fails is dropped on its own, the others carry on
+        for (&peer_id, remote) in &mut self.peers {
+            let result = remote
+                .peer
+                .tick(now)
+                .and_then(|()| remote.peer.keepalive(now, PEER_TIMEOUT));
+
+            match result {
+                Ok(false) => {}
+                Ok(true) => dropped.push((peer_id, DisconnectReason::Timeout)),
+                Err(error) => dropped.push((peer_id, DisconnectReason::Transport(error))),
+            }
+        }
+
+        for (peer_id, reason) in dropped {
+            self.remove_peer(peer_id, reason);
+        }
+
+        Ok(())
+    }
+
+    fn flush_datagrams(&mut self) {
+        for remote in self.peers.values_mut() {
+            let to = remote.address;
+            let datagrams = remote.peer.poll_datagrams().map(|data| ServerOutput::Send { to, data });
+            self.output_queue.extend(datagrams);
+        }
+    }
+
+    pub fn handle_input(&mut self, input: ServerInput) -> Result<()> {
+        let result = match input {
+            ServerInput::Receive { from, data } => self.handle_serverbound_data(from, data),
+            ServerInput::Packet {
+                peer_id,
+                is_reliable,
+                packet,
+            } => self.send_packet(peer_id, is_reliable, packet),
+            ServerInput::Disconnect { peer_id } => self.disconnect(peer_id),
+            ServerInput::Tick(now) => self.handle_tick(now),
+        };
+
+        self.flush_datagrams();
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::SEQNUM_INITIAL;
+    use crate::frame::Reliability;
+    use crate::serverbound::Init2;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 30001));
+
+    fn reliable_frame(seqnum: u16, ty: FrameType, payload: &[u8]) -> Vec<u8> {
+        let header = FrameHeader {
+            peer_id: PEER_ID_INEXISTENT,
+            channel: 0,
+            reliability: Reliability::Reliable { seqnum },
+            ty,
+        };
+
+        let mut data = Vec::new();
+        header.serialize(&mut data).unwrap();
+        data.extend(payload);
+        data
+    }
+
+    #[test]
+    fn packets_after_disco_are_dropped() {
+        let mut server = Server::new();
+
+        // Waits for the frame before it
+        let mut packet = Vec::new();
+        ServerBound::from(Init2 {
+            language_code: "de".to_string(),
+        })
+        .serialize(&mut packet)
+        .unwrap();
+        let data = reliable_frame(SEQNUM_INITIAL.wrapping_add(1), FrameType::Original, &packet);
+        server
+            .handle_input(ServerInput::Receive {
+                from: ADDRESS,
+                data: &data,
+            })
+            .unwrap();
+
+        let data = reliable_frame(SEQNUM_INITIAL, FrameType::Control(Control::Disco), &[]);
+        server
+            .handle_input(ServerInput::Receive {
+                from: ADDRESS,
+                data: &data,
+            })
+            .unwrap();
+
+        let outputs: Vec<_> = server.poll_output().collect();
+        assert!(outputs.iter().any(|output| matches!(
+            output,
+            ServerOutput::Disconnected {
+                reason: DisconnectReason::Closed,
+                ..
+            }
+        )));
+        assert!(!outputs
+            .iter()
+            .any(|output| matches!(output, ServerOutput::Packet { .. })));
+        assert_eq!(server.peers().count(), 0);
+    }
+}
@@ -1,5 +1,5 @@
 use mtt_macros::{packet, Serialize};
-use mtt_serialize::{RawBytes16, RawBytesUnsized, Serialize};
+use mtt_serialize::{RawBytes16, RawBytesUnsized, Result, Serialize};
 use std::io::{Read, Write};
 
 #[derive(Debug, Clone, Serialize)]
@@ -31,19 +31,14 @@ pub struct RequestMedia {
 }
 
 impl Serialize for RequestMedia {
-    fn serialize<W: Write>(&self, w: &mut W) -> anyhow::Result<()> {
-        let len: u16 = self.media.len().try_into()?;
-        len.serialize(w)?;
-
-        for elem in &self.media {
-            elem.serialize(w)?;
-        }
-
-        Ok(())
+    fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
+        self.media.serialize(w)
     }
 
-    fn deserialize<R: Read>(_r: &mut R) -> anyhow::Result<Self> {
-        todo!()
+    fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        Ok(Self {
+            media: Vec::<String>::deserialize(r)?,
+        })
     }
 }
 
@@ -57,6 +52,14 @@ pub struct ClientReady {
     pub formspec_version: u16,
 }
 
+/// Registers the account with a verifier generated by the client.
+#[derive(Debug, Clone, Serialize)]
+pub struct FirstSrp {
+    pub salt: RawBytes16,
+    pub verifier: RawBytes16,
+    pub is_empty: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SrpBytesA {
     pub data: RawBytes16,
@@ -89,9 +92,29 @@ pub enum ServerBound {
     #[id = 0x43]
     ClientReady(ClientReady),
 
+    #[id = 0x50]
+    FirstSrp(FirstSrp),
+
     #[id = 0x51]
     SrpBytesA(SrpBytesA),
 
     #[id = 0x52]
     SrpBytesM(SrpBytesM),
 }
+
+impl ServerBound {
+    /// Channel on which the server expects to receive this packet.
+    pub fn channel(&self) -> u8 {
+        match self {
+            ServerBound::Handshake(_) => 0,
+            ServerBound::Init(_) => 1,
+            ServerBound::Init2(_) => 1,
+            ServerBound::GotBlocks(_) => 2,
+            ServerBound::RequestMedia(_) => 1,
+            ServerBound::ClientReady(_) => 1,
+            ServerBound::FirstSrp(_) => 1,
+            ServerBound::SrpBytesA(_) => 1,
+            ServerBound::SrpBytesM(_) => 1,
+        }
+    }
+}
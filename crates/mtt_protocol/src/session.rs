@@ -0,0 +1,531 @@
+use std::collections::HashSet;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::auth::{
+    generate_verifier, AuthMechanisms, SrpClient, SrpError, SrpGroup, PRIVATE_EPHEMERAL_SIZE, SALT_SIZE,
+};
+use crate::clientbound::{AccessDenied, ClientBound};
+use crate::peer::Peer;
+use crate::serverbound;
+use crate::{Client, Error, Output, Result};
+use mtt_serialize::{Limits, Versions};
+use sha2::Sha256;
+
+/// Serialization versions from Minetest 5.4 onwards are understood.
+pub const SERIALIZATION_VERSION_MIN: u8 = 28;
+pub const SERIALIZATION_VERSION_MAX: u8 = 29;
+/// Protocol versions of Minetest 5.4 to 5.8. Packets the client understands
+/// only gained trailing fields in between, which are read if present or
+/// checked against `Versions::protocol`.
+pub const PROTOCOL_VERSION_MIN: u16 = 39;
+pub const PROTOCOL_VERSION_MAX: u16 = 43;
+pub const FORMSPEC_VERSION: u16 = 7;
+
+/// Init is sent unreliably, so it's repeated until the server responds.
+const INIT_RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub player_name: String,
+    pub password: String,
+    /// Whether to create the account right away if it doesn't exist yet,
+    /// instead of waiting for [`Input::ConfirmRegistration`](crate::Input::ConfirmRegistration).
+    pub auto_register: bool,
+    pub language_code: String,
+    /// Whether to download media announced by the server. Headless clients
+    /// usually don't need textures and sounds.
+    pub fetch_media: bool,
+    /// Connection is dropped if nothing is received from the server for
+    /// this long.
+    pub timeout: Duration,
+    /// Bounds on allocations made while decoding packets from the server.
+    pub limits: Limits,
+}
+
+impl ClientConfig {
+    pub fn new(player_name: impl Into<String>) -> Self {
+        Self {
+            player_name: player_name.into(),
+            password: String::new(),
+            auto_register: false,
+            language_code: String::new(),
+            fetch_media: true,
+            timeout: Duration::from_secs(30),
+            limits: Limits::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Nothing was sent yet.
+    Start,
+    /// Waiting for the server to assign a peer ID.
+    Handshake,
+    /// Waiting for `Hello`.
+    Init,
+    /// Account doesn't exist, waiting for the user to confirm registration.
+    Register,
+    /// Waiting for `AuthAccept`.
+    Auth,
+    /// Waiting for item and node definitions and media announcement.
+    Definitions,
+    /// Waiting for requested media files.
+    Media,
+    InGame,
+    Disconnected,
+}
+
+impl ConnectionState {
+    /// Time the server has to complete the step before the client gives up.
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            ConnectionState::Handshake => Some(Duration::from_secs(10)),
+            ConnectionState::Init => Some(Duration::from_secs(10)),
+            ConnectionState::Auth => Some(Duration::from_secs(30)),
+            ConnectionState::Definitions => Some(Duration::from_secs(60)),
+            ConnectionState::Media => Some(Duration::from_secs(300)),
+            ConnectionState::Start
+            | ConnectionState::Register
+            | ConnectionState::InGame
+            | ConnectionState::Disconnected => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("timed out in {0:?} state")]
+    Timeout(ConnectionState),
+    #[error("unsupported protocol version: {0}")]
+    UnsupportedProtocolVersion(u16),
+    #[error("unsupported serialization version: {0}")]
+    UnsupportedSerializationVersion(u8),
+    #[error("no supported authentication mechanism offered: {0:?}")]
+    UnsupportedAuthMechanisms(AuthMechanisms),
+    #[error("authentication failed: {0}")]
+    Srp(#[from] SrpError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DisconnectReason {
+    #[error("connection was closed by the client")]
+    Local,
+    #[error("server closed the connection")]
+    Closed,
+    #[error("peer was disconnected by the server")]
+    Kicked,
+    #[error("server stopped responding")]
+    Timeout,
+    #[error("access denied: {0}")]
+    AccessDenied(AccessDenied),
+    #[error(transparent)]
+    Session(#[from] SessionError),
+    #[error("transport failed: {0}")]
+    Transport(Error),
+}
+
+impl DisconnectReason {
+    /// Whether connecting again is likely to succeed.
+    pub fn reconnect(&self) -> bool {
+        match self {
+            DisconnectReason::AccessDenied(denied) => denied.reconnect,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Loading {
+    item_def: bool,
+    node_def: bool,
+    media_announced: bool,
+    media_bunch_count: Option<u16>,
+    media_bunches: HashSet<u16>,
+}
+
+impl Loading {
+    fn media_done(&self) -> bool {
+        match self.media_bunch_count {
+            Some(count) => self.media_bunches.len() >= count as usize,
+            None => false,
+        }
+    }
+}
+
+pub(crate) struct Session {
+    config: ClientConfig,
+    state: ConnectionState,
+    state_changed_at: Instant,
+    init_sent_at: Instant,
+    loading: Loading,
+    /// Versions the server picked in `Hello`.
+    versions: Option<Versions>,
+    srp: Option<SrpClient>,
+}
+
+impl Session {
+    pub fn new(config: ClientConfig, now: Instant) -> Self {
+        Self {
+            config,
+            state: ConnectionState::Start,
+            state_changed_at: now,
+            init_sent_at: now,
+            loading: Loading::default(),
+            versions: None,
+            srp: None,
+        }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn versions(&self) -> Option<Versions> {
+        self.versions
+    }
+}
+
+impl Client {
+    fn set_state(&mut self, state: ConnectionState) {
+        self.session.state = state;
+        self.session.state_changed_at = self.now;
+        self.output_queue.push_back(Output::StateChanged(state));
+    }
+
+    pub(crate) fn disconnect(&mut self, reason: DisconnectReason) {
+        self.output_queue.push_back(Output::Disconnected(reason));
+        self.set_state(ConnectionState::Disconnected);
+    }
+
+    fn fail(&mut self, error: SessionError) {
+        self.disconnect(error.into());
+    }
+
+    pub(crate) fn session_connect(&mut self) -> Result<()> {
+        match self.session.state {
+            // Starts with a fresh session, so that time spent before connecting
+            // doesn't count towards timeouts. Also used to start over, e.g.
+            // when the server suggested reconnecting
+            ConnectionState::Start | ConnectionState::Disconnected => {
+                self.session = Session::new(self.session.config.clone(), self.now);
+                self.peer = Peer::new(0, self.now);
+            }
+            // The session carries on
+            state => return Err(Error::AlreadyConnected(state)),
+        }
+
+        self.send_packet(true, serverbound::Handshake {}.into())?;
+        self.set_state(ConnectionState::Handshake);
+
+        Ok(())
+    }
+
+    fn send_init(&mut self) -> Result<()> {
+        let init = serverbound::Init {
+            max_serialization_version: SERIALIZATION_VERSION_MAX,
+            supported_compression_modes: 0,
+            min_protocol_version: PROTOCOL_VERSION_MIN,
+            max_protocol_version: PROTOCOL_VERSION_MAX,
+            player_name: self.session.config.player_name.clone(),
+        };
+
+        self.session.init_sent_at = self.now;
+        self.send_packet(false, init.into())
+    }
+
+    fn start_srp(&mut self) -> Result<()> {
+        let mut a = [0; PRIVATE_EPHEMERAL_SIZE];
+        getrandom::getrandom(&mut a).map_err(io::Error::from)?;
+
+        let config = &self.session.config;
+        let srp = SrpClient::new(SrpGroup::ng_2048(), &config.player_name, &config.password, &a);
+        let bytes_a = serverbound::SrpBytesA {
+            data: srp.public_ephemeral().into(),
+            // Verifier is based on SRP rather than on a legacy password hash
+            based_on: 1,
+        };
+
+        self.session.srp = Some(srp);
+        self.send_packet(true, bytes_a.into())
+    }
+
+    fn register(&mut self) -> Result<()> {
+        let mut salt = [0; SALT_SIZE];
+        getrandom::getrandom(&mut salt).map_err(io::Error::from)?;
+
+        let config = &self.session.config;
+        let verifier = generate_verifier::<Sha256>(&SrpGroup::ng_2048(), &config.player_name, &config.password, &salt);
+        let first_srp = serverbound::FirstSrp {
+            salt: salt.to_vec().into(),
+            verifier: verifier.into(),
+            is_empty: config.password.is_empty(),
+        };
+
+        self.send_packet(true, first_srp.into())
+    }
+
+    pub(crate) fn session_confirm_registration(&mut self, password: &str) -> Result<()> {
+        if self.session.state != ConnectionState::Register {
+            return Ok(());
+        }
+
+        if password != self.session.config.password {
+            self.output_queue.push_back(Output::PasswordMismatch);
+            return Ok(());
+        }
+
+        self.register()?;
+        self.set_state(ConnectionState::Auth);
+
+        Ok(())
+    }
+
+    pub(crate) fn session_peer_id_assigned(&mut self) -> Result<()> {
+        if self.session.state == ConnectionState::Handshake {
+            self.send_init()?;
+            self.set_state(ConnectionState::Init);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn session_tick(&mut self) -> Result<()> {
+        let state = self.session.state;
+
+        if let Some(timeout) = state.timeout() {
+            if self.now.duration_since(self.session.state_changed_at) >= timeout {
+                self.fail(SessionError::Timeout(state));
+                return Ok(());
+            }
+        }
+
+        let since_init = self.now.duration_since(self.session.init_sent_at);
+        if state == ConnectionState::Init && since_init >= INIT_RESEND_INTERVAL {
+            self.send_init()?;
+        }
+
+        Ok(())
+    }
+
+    /// When `session_tick` has to run next, if it has anything to do.
+    pub(crate) fn session_deadline(&self) -> Option<Instant> {
+        let state = self.session.state;
+        let timeout = state.timeout().map(|timeout| self.session.state_changed_at + timeout);
+        let init_resend = (state == ConnectionState::Init).then(|| self.session.init_sent_at + INIT_RESEND_INTERVAL);
+
+        timeout.into_iter().chain(init_resend).min()
+    }
+
+    pub(crate) fn session_handle_packet(&mut self, packet: &ClientBound) -> Result<()> {
+        match (self.session.state, packet) {
+            (ConnectionState::Disconnected, _) => {}
+            (_, ClientBound::AccessDenied(denied)) => {
+                self.disconnect(DisconnectReason::AccessDenied(denied.clone()));
+            }
+            (ConnectionState::Init, ClientBound::Hello(hello)) => {
+                let protocol_version = hello.protocol_version;
+                let serialization_version = hello.serialization_version;
+
+                if !(PROTOCOL_VERSION_MIN..=PROTOCOL_VERSION_MAX).contains(&protocol_version) {
+                    self.fail(SessionError::UnsupportedProtocolVersion(protocol_version));
+                } else if !(SERIALIZATION_VERSION_MIN..=SERIALIZATION_VERSION_MAX).contains(&serialization_version) {
+                    self.fail(SessionError::UnsupportedSerializationVersion(serialization_version));
+                } else {
+                    self.session.versions = Some(Versions {
+                        protocol: protocol_version,
+                        serialization: serialization_version,
+                    });
+
+                    let mechanisms = AuthMechanisms::from_bits_truncate(hello.supported_auth_modes);
+                    if mechanisms.contains(AuthMechanisms::SRP) {
+                        self.start_srp()?;
+                        self.set_state(ConnectionState::Auth);
+                    } else if mechanisms.contains(AuthMechanisms::FIRST_SRP) {
+                        if self.session.config.auto_register {
+                            self.register()?;
+                            self.set_state(ConnectionState::Auth);
+                        } else {
+                            self.set_state(ConnectionState::Register);
+                        }
+                    } else {
+                        self.fail(SessionError::UnsupportedAuthMechanisms(mechanisms));
+                    }
+                }
+            }
+            (ConnectionState::Auth, ClientBound::SrpBytesSB(challenge)) => {
+                let Some(srp) = self.session.srp.take() else {
+                    return Ok(());
+                };
+
+                match srp.process_challenge(&challenge.s.0, &challenge.b.0) {
+                    Ok(secrets) => {
+                        let bytes_m = serverbound::SrpBytesM {
+                            data: secrets.proof.into(),
+                        };
+
+                        self.send_packet(true, bytes_m.into())?;
+                    }
+                    Err(error) => self.fail(error.into()),
+                }
+            }
+            (ConnectionState::Auth, ClientBound::AuthAccept(_)) => {
+                let init2 = serverbound::Init2 {
+                    language_code: self.session.config.language_code.clone(),
+                };
+
+                self.send_packet(true, init2.into())?;
+                self.set_state(ConnectionState::Definitions);
+            }
+            (ConnectionState::Definitions | ConnectionState::Media, ClientBound::ItemDef(_)) => {
+                self.session.loading.item_def = true;
+                self.try_finish_loading()?;
+            }
+            (ConnectionState::Definitions | ConnectionState::Media, ClientBound::NodeDef(_)) => {
+                self.session.loading.node_def = true;
+                self.try_finish_loading()?;
+            }
+            (ConnectionState::Definitions, ClientBound::AnnounceMedia(announce)) => {
+                let loading = &mut self.session.loading;
+                loading.media_announced = true;
+
+                if self.session.config.fetch_media && !announce.digests.is_empty() {
+                    let request = serverbound::RequestMedia {
+                        media: announce.digests.keys().cloned().collect(),
+                    };
+
+                    self.send_packet(true, request.into())?;
+                    self.set_state(ConnectionState::Media);
+                } else {
+                    loading.media_bunch_count = Some(0);
+                    self.try_finish_loading()?;
+                }
+            }
+            (ConnectionState::Media, ClientBound::Media(media)) => {
+                let loading = &mut self.session.loading;
+                loading.media_bunch_count = Some(media.bunch_count);
+                loading.media_bunches.insert(media.bunch_id);
+
+                self.try_finish_loading()?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn try_finish_loading(&mut self) -> Result<()> {
+        let loading = &self.session.loading;
+        if !(loading.item_def && loading.node_def && loading.media_announced && loading.media_done()) {
+            return Ok(());
+        }
+
+        let client_ready = serverbound::ClientReady {
+            version_major: 5,
+            version_minor: 8,
+            version_patch: 0,
+            reserved: 0,
+            full_version: format!("mtt {}", env!("CARGO_PKG_VERSION")),
+            formspec_version: FORMSPEC_VERSION,
+        };
+
+        self.send_packet(true, client_ready.into())?;
+        self.set_state(ConnectionState::InGame);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clientbound::Hello;
+    use crate::Input;
+
+    #[test]
+    fn time_before_connecting_doesnt_count_towards_timeouts() {
+        let mut client = Client::new(ClientConfig::new("player"));
+        let later = client.now + Duration::from_secs(60);
+
+        client.handle_input(Input::Tick(later)).unwrap();
+        client.handle_input(Input::Connect).unwrap();
+        client
+            .handle_input(Input::Tick(later + Duration::from_secs(1)))
+            .unwrap();
+
+        assert_eq!(client.state(), ConnectionState::Handshake);
+    }
+
+    fn hello(protocol_version: u16) -> ClientBound {
+        ClientBound::Hello(Hello {
+            serialization_version: SERIALIZATION_VERSION_MAX,
+            compression_mode: 0,
+            protocol_version,
+            supported_auth_modes: AuthMechanisms::SRP.bits(),
+            legacy_player_name: "player".to_string(),
+        })
+    }
+
+    #[test]
+    fn hello_outside_the_supported_range_is_rejected() {
+        for protocol_version in [PROTOCOL_VERSION_MIN - 1, PROTOCOL_VERSION_MAX + 1] {
+            let mut client = Client::new(ClientConfig::new("player"));
+            client.handle_input(Input::Connect).unwrap();
+            client.set_state(ConnectionState::Init);
+            client.poll_output().for_each(drop);
+
+            client.session_handle_packet(&hello(protocol_version)).unwrap();
+
+            assert_eq!(client.state(), ConnectionState::Disconnected);
+            assert_eq!(client.versions(), None);
+            assert!(client.poll_output().any(|output| matches!(
+                output,
+                Output::Disconnected(DisconnectReason::Session(SessionError::UnsupportedProtocolVersion(version)))
+                    if version == protocol_version
+            )));
+        }
+
+        let mut client = Client::new(ClientConfig::new("player"));
+        client.handle_input(Input::Connect).unwrap();
+        client.set_state(ConnectionState::Init);
+
+        client.session_handle_packet(&hello(PROTOCOL_VERSION_MAX)).unwrap();
+
+        assert_eq!(client.state(), ConnectionState::Auth);
+        assert_eq!(client.versions().unwrap().protocol, PROTOCOL_VERSION_MAX);
+    }
+
+    #[test]
+    fn connecting_twice_keeps_the_session() {
+        let mut client = Client::new(ClientConfig::new("player"));
+        client.handle_input(Input::Connect).unwrap();
+        client.poll_output().for_each(drop);
+
+        let error = client.handle_input(Input::Connect).unwrap_err();
+        assert!(matches!(error, Error::AlreadyConnected(ConnectionState::Handshake)));
+
+        assert_eq!(client.state(), ConnectionState::Handshake);
+        assert_eq!(client.poll_output().count(), 0);
+    }
+
+    #[test]
+    fn ticking_at_the_deadline_resends() {
+        let mut client = Client::new(ClientConfig::new("player"));
+        assert_eq!(client.next_deadline(), None);
+
+        client.handle_input(Input::Connect).unwrap();
+        client.poll_output().for_each(drop);
+
+        let deadline = client.next_deadline().unwrap();
+        assert!(deadline > client.now);
+        client.handle_input(Input::Tick(deadline)).unwrap();
+
+        assert!(client.poll_output().any(|output| matches!(output, Output::Send(_))));
+        assert!(client.next_deadline().unwrap() > deadline);
+    }
+}
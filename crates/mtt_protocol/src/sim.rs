@@ -0,0 +1,547 @@
+//! In-memory network for exercising the transport without sockets.
+//!
+//! Everything is driven by a virtual clock and a seeded RNG, so a given
+//! seed always reproduces the same losses, duplicates and delays.
+
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+use crate::server::{ServerInput, ServerOutput};
+use crate::{Client, ClientConfig, Input, Output, Result, Server};
+
+/// Address the simulated client appears to have on the server side.
+pub const CLIENT_ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 30001));
+
+/// SplitMix64, which is good enough for picking fates of datagrams.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `0.0..1.0`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+
+    fn duration(&mut self, max: Duration) -> Duration {
+        max.mul_f64(self.next_f64())
+    }
+}
+
+/// Conditions of a single direction of a link.
+#[derive(Debug, Clone)]
+pub struct LinkConfig {
+    /// Probability of a datagram being dropped.
+    pub loss: f64,
+    /// Probability of a datagram being delivered twice.
+    pub duplication: f64,
+    /// Probability of a datagram being held back, so that the ones sent
+    /// after it overtake it.
+    pub reordering: f64,
+    /// How long a held back datagram is delayed at most, on top of latency.
+    pub reorder_delay: Duration,
+    pub latency: Duration,
+    /// Random delay added to latency, up to this long.
+    pub jitter: Duration,
+    /// Datagrams larger than this are dropped.
+    pub mtu: usize,
+}
+
+impl LinkConfig {
+    /// Link which delivers everything instantly.
+    pub fn perfect() -> Self {
+        Self {
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            reorder_delay: Duration::from_millis(100),
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            mtu: usize::MAX,
+        }
+    }
+}
+
+struct InTransit {
+    deliver_at: Instant,
+    /// Keeps datagrams due at the same time in the order they were sent.
+    order: u64,
+    data: Vec<u8>,
+}
+
+/// One direction of a simulated network path.
+pub struct Link {
+    config: LinkConfig,
+    rng: Rng,
+    in_transit: Vec<InTransit>,
+    sent_count: u64,
+}
+
+impl Link {
+    pub fn new(config: LinkConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: Rng(seed),
+            in_transit: Vec::new(),
+            sent_count: 0,
+        }
+    }
+
+    pub fn config(&self) -> &LinkConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: LinkConfig) {
+        self.config = config;
+    }
+
+    pub fn send(&mut self, data: &[u8], now: Instant) {
+        if data.len() > self.config.mtu || self.rng.chance(self.config.loss) {
+            return;
+        }
+
+        let copies = if self.rng.chance(self.config.duplication) { 2 } else { 1 };
+
+        for _ in 0..copies {
+            let mut delay = self.config.latency + self.rng.duration(self.config.jitter);
+            if self.rng.chance(self.config.reordering) {
+                delay += self.rng.duration(self.config.reorder_delay);
+            }
+
+            self.in_transit.push(InTransit {
+                deliver_at: now + delay,
+                order: self.sent_count,
+                data: data.to_vec(),
+            });
+            self.sent_count += 1;
+        }
+    }
+
+    /// Removes and returns datagrams which have arrived by `now`.
+    pub fn poll(&mut self, now: Instant) -> Vec<Vec<u8>> {
+        let (mut arrived, in_transit) = std::mem::take(&mut self.in_transit)
+            .into_iter()
+            .partition::<Vec<_>, _>(|datagram| datagram.deliver_at <= now);
+
+        self.in_transit = in_transit;
+        arrived.sort_by_key(|datagram| (datagram.deliver_at, datagram.order));
+
+        arrived.into_iter().map(|datagram| datagram.data).collect()
+    }
+
+    /// Number of datagrams which were sent but haven't arrived yet.
+    pub fn in_transit(&self) -> usize {
+        self.in_transit.len()
+    }
+}
+
+/// Remote end of a simulation. Implemented by [`Server`], and by
+/// [`ScriptedPeer`] for tests which need exact control over what the client
+/// receives.
+pub trait Remote {
+    fn handle_input(&mut self, input: ServerInput) -> Result<()>;
+
+    /// Outputs produced since the last call.
+    fn poll_output(&mut self) -> Vec<ServerOutput>;
+}
+
+impl Remote for Server {
+    fn handle_input(&mut self, input: ServerInput) -> Result<()> {
+        Server::handle_input(self, input)
+    }
+
+    fn poll_output(&mut self) -> Vec<ServerOutput> {
+        Server::poll_output(self).collect()
+    }
+}
+
+/// Remote which answers every input with whatever the script returns, e.g.
+/// hand-crafted datagrams for [`CLIENT_ADDRESS`], or nothing at all.
+pub struct ScriptedPeer<F> {
+    script: F,
+    outputs: Vec<ServerOutput>,
+}
+
+impl<F: FnMut(ServerInput) -> Vec<ServerOutput>> ScriptedPeer<F> {
+    pub fn new(script: F) -> Self {
+        Self {
+            script,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+impl<F: FnMut(ServerInput) -> Vec<ServerOutput>> Remote for ScriptedPeer<F> {
+    fn handle_input(&mut self, input: ServerInput) -> Result<()> {
+        let outputs = (self.script)(input);
+        self.outputs.extend(outputs);
+
+        Ok(())
+    }
+
+    fn poll_output(&mut self) -> Vec<ServerOutput> {
+        std::mem::take(&mut self.outputs)
+    }
+}
+
+/// A [`Client`] and a [`Remote`], by default a [`Server`], connected by a
+/// pair of simulated links.
+///
+/// Datagrams are routed between the two automatically, all other outputs
+/// are kept for inspection.
+pub struct Simulation<R = Server> {
+    pub client: Client,
+    pub server: R,
+    pub to_server: Link,
+    pub to_client: Link,
+    client_outputs: VecDeque<Output>,
+    server_outputs: VecDeque<ServerOutput>,
+    now: Instant,
+}
+
+impl Simulation {
+    pub fn new(config: ClientConfig, to_server: LinkConfig, to_client: LinkConfig, seed: u64) -> Self {
+        let now = Instant::now();
+        Self::build(config, Server::new_at(now), to_server, to_client, seed, now)
+    }
+}
+
+impl<R: Remote> Simulation<R> {
+    /// Connects the client to `server` instead of a [`Server`].
+    pub fn with_remote(
+        config: ClientConfig,
+        server: R,
+        to_server: LinkConfig,
+        to_client: LinkConfig,
+        seed: u64,
+    ) -> Self {
+        Self::build(config, server, to_server, to_client, seed, Instant::now())
+    }
+
+    fn build(
+        config: ClientConfig,
+        server: R,
+        to_server: LinkConfig,
+        to_client: LinkConfig,
+        seed: u64,
+        now: Instant,
+    ) -> Self {
+        // Each direction needs its own stream of random numbers
+        let mut rng = Rng(seed);
+
+        Self {
+            client: Client::new_at(config, now),
+            server,
+            to_server: Link::new(to_server, rng.next_u64()),
+            to_client: Link::new(to_client, rng.next_u64()),
+            client_outputs: VecDeque::new(),
+            server_outputs: VecDeque::new(),
+            now,
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// Outputs of the client, except for datagrams.
+    pub fn poll_client_output(&mut self) -> impl Iterator<Item = Output> + '_ {
+        self.client_outputs.drain(..)
+    }
+
+    /// Outputs of the server, except for datagrams.
+    pub fn poll_server_output(&mut self) -> impl Iterator<Item = ServerOutput> + '_ {
+        self.server_outputs.drain(..)
+    }
+
+    fn route(&mut self) {
+        for output in self.client.poll_output() {
+            match output {
+                Output::Send(data) => self.to_server.send(&data, self.now),
+                output => self.client_outputs.push_back(output),
+            }
+        }
+
+        for output in self.server.poll_output() {
+            match output {
+                ServerOutput::Send { to, data } if to == CLIENT_ADDRESS => self.to_client.send(&data, self.now),
+                ServerOutput::Send { .. } => {}
+                output => self.server_outputs.push_back(output),
+            }
+        }
+    }
+
+    /// Advances the clock by `dt`, ticks both sides and delivers datagrams
+    /// which have arrived in the meantime.
+    pub fn step(&mut self, dt: Duration) -> Result<()> {
+        // Pick up anything the caller has fed into the client or the server directly
+        self.route();
+
+        self.now += dt;
+
+        self.client.handle_input(Input::Tick(self.now))?;
+        self.server.handle_input(ServerInput::Tick(self.now))?;
+        self.route();
+
+        for data in self.to_server.poll(self.now) {
+            let input = ServerInput::Receive {
+                from: CLIENT_ADDRESS,
+                data: &data,
+            };
+
+            self.server.handle_input(input)?;
+        }
+
+        for data in self.to_client.poll(self.now) {
+            self.client.handle_input(Input::Receive(&data))?;
+        }
+
+        self.route();
+
+        Ok(())
+    }
+
+    /// Steps the simulation until `duration` of virtual time has passed.
+    pub fn run_for(&mut self, duration: Duration, dt: Duration) -> Result<()> {
+        let end = self.now + duration;
+
+        while self.now < end {
+            self.step(dt)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::SEQNUM_INITIAL;
+    use crate::clientbound::{ChatMessage, ClientBound};
+    use crate::frame::{Control, FrameHeader, FrameType, Reliability, MAX_PACKET_SIZE};
+    use crate::serverbound::{self, ServerBound};
+    use crate::{ConnectionState, DisconnectReason, SessionError};
+    use mtt_serialize::Serialize;
+
+    const STEP: Duration = Duration::from_millis(10);
+
+    fn lossy() -> LinkConfig {
+        LinkConfig {
+            loss: 0.2,
+            duplication: 0.1,
+            reordering: 0.2,
+            latency: Duration::from_millis(30),
+            jitter: Duration::from_millis(20),
+            ..LinkConfig::perfect()
+        }
+    }
+
+    fn chat(message: String) -> ClientBound {
+        ClientBound::ChatMessage(ChatMessage {
+            version: 1,
+            ty: 0,
+            sender: String::new(),
+            message,
+            time: 0,
+        })
+    }
+
+    /// Connects a client to a server, sends `messages` reliably from the
+    /// server and returns the messages the client received.
+    fn deliver(link: LinkConfig, seed: u64, messages: &[String]) -> (Vec<String>, Simulation) {
+        let mut sim = Simulation::new(ClientConfig::new("player"), link.clone(), link, seed);
+        sim.client.handle_input(Input::Connect).unwrap();
+
+        let mut peer_id = None;
+        while peer_id.is_none() {
+            sim.step(STEP).unwrap();
+            peer_id = sim.poll_server_output().find_map(|output| match output {
+                ServerOutput::Connected { peer_id, .. } => Some(peer_id),
+                _ => None,
+            });
+        }
+
+        for message in messages {
+            let input = ServerInput::Packet {
+                peer_id: peer_id.unwrap(),
+                is_reliable: true,
+                packet: chat(message.clone()),
+            };
+            sim.server.handle_input(input).unwrap();
+        }
+
+        // Well within the time the client waits for `Hello`
+        sim.run_for(Duration::from_secs(5), STEP).unwrap();
+
+        let received = sim
+            .poll_client_output()
+            .filter_map(|output| match output {
+                Output::Packet(ClientBound::ChatMessage(chat)) => Some(chat.message),
+                _ => None,
+            })
+            .collect();
+
+        (received, sim)
+    }
+
+    #[test]
+    fn lossy_links_deliver_everything_in_order() {
+        let messages: Vec<_> = (0..20).map(|i| i.to_string()).collect();
+
+        for seed in 0..8 {
+            let (received, sim) = deliver(lossy(), seed, &messages);
+
+            assert_eq!(received, messages, "seed {seed}");
+            assert_eq!(sim.client.state(), ConnectionState::Init, "seed {seed}");
+
+            // The server had to resend something, so the link did lose frames
+            let (peer_id, _) = sim.server.peers().next().unwrap();
+            assert!(sim.server.stats(peer_id).unwrap().loss_rate > 0.0, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn lossy_links_deliver_serverbound_packets_in_order() {
+        let messages: Vec<_> = (0..20).map(|i| i.to_string()).collect();
+
+        for seed in 0..8 {
+            let mut sim = Simulation::new(ClientConfig::new("player"), lossy(), lossy(), seed);
+            sim.client.handle_input(Input::Connect).unwrap();
+
+            for message in &messages {
+                let packet = serverbound::Init2 {
+                    language_code: message.clone(),
+                };
+                let input = Input::Packet {
+                    is_reliable: true,
+                    packet: packet.into(),
+                };
+                sim.client.handle_input(input).unwrap();
+            }
+
+            sim.run_for(Duration::from_secs(5), STEP).unwrap();
+
+            let received: Vec<_> = sim
+                .poll_server_output()
+                .filter_map(|output| match output {
+                    ServerOutput::Packet {
+                        packet: ServerBound::Init2(init2),
+                        ..
+                    } => Some(init2.language_code),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(received, messages, "seed {seed}");
+
+            // The client had to resend something, so the link did lose frames
+            assert!(sim.client.stats().loss_rate > 0.0, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn large_packets_are_split_to_fit_the_mtu() {
+        let link = LinkConfig {
+            mtu: MAX_PACKET_SIZE,
+            ..lossy()
+        };
+        let messages: Vec<_> = (0..5).map(|i| i.to_string().repeat(2000)).collect();
+
+        for seed in 0..4 {
+            let (received, _) = deliver(link.clone(), seed, &messages);
+            assert_eq!(received, messages, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn delivery_continues_across_seqnum_wraparound() {
+        let count = 200;
+        // Channel 0 also carries `SetPeerId`, so this crosses zero
+        assert!(SEQNUM_INITIAL as usize + count > u16::MAX as usize);
+
+        let messages: Vec<_> = (0..count).map(|i| i.to_string()).collect();
+        let (received, _) = deliver(lossy(), 7, &messages);
+
+        assert_eq!(received, messages);
+    }
+
+    #[test]
+    fn fixed_seeds_reproduce_the_same_run() {
+        let messages: Vec<_> = (0..20).map(|i| i.to_string()).collect();
+
+        let (_, first) = deliver(lossy(), 42, &messages);
+        let (_, second) = deliver(lossy(), 42, &messages);
+
+        assert_eq!(
+            format!("{:?}", first.client.stats()),
+            format!("{:?}", second.client.stats())
+        );
+    }
+
+    #[test]
+    fn silent_peer_times_out_the_handshake() {
+        let peer = ScriptedPeer::new(|_| Vec::new());
+        let link = LinkConfig::perfect();
+        let mut sim = Simulation::with_remote(ClientConfig::new("player"), peer, link.clone(), link, 0);
+
+        sim.client.handle_input(Input::Connect).unwrap();
+        sim.run_for(Duration::from_secs(11), STEP).unwrap();
+
+        let reason = sim.poll_client_output().find_map(|output| match output {
+            Output::Disconnected(reason) => Some(reason),
+            _ => None,
+        });
+        assert!(matches!(
+            reason,
+            Some(DisconnectReason::Session(SessionError::Timeout(
+                ConnectionState::Handshake
+            )))
+        ));
+    }
+
+    #[test]
+    fn scripted_disco_closes_the_connection() {
+        let peer = ScriptedPeer::new(|input| match input {
+            ServerInput::Receive { .. } => {
+                let header = FrameHeader {
+                    peer_id: crate::server::PEER_ID_SERVER,
+                    channel: 0,
+                    reliability: Reliability::Unreliable,
+                    ty: FrameType::Control(Control::Disco),
+                };
+                let mut data = Vec::new();
+                header.serialize(&mut data).unwrap();
+
+                vec![ServerOutput::Send {
+                    to: CLIENT_ADDRESS,
+                    data,
+                }]
+            }
+            _ => Vec::new(),
+        });
+        let link = LinkConfig {
+            latency: Duration::from_millis(50),
+            ..LinkConfig::perfect()
+        };
+        let mut sim = Simulation::with_remote(ClientConfig::new("player"), peer, link.clone(), link, 0);
+
+        sim.client.handle_input(Input::Connect).unwrap();
+        sim.run_for(Duration::from_millis(200), STEP).unwrap();
+
+        assert_eq!(sim.client.state(), ConnectionState::Disconnected);
+        assert!(sim
+            .poll_client_output()
+            .any(|output| matches!(output, Output::Disconnected(DisconnectReason::Closed))));
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::frame::{Split, BASE_HEADER_SIZE, MAX_PACKET_SIZE, RELIABLE_HEADER_SIZE, SPLIT_HEADER_SIZE};
+use crate::{Error, Result};
+
+/// Incomplete packets are discarded if no chunks arrive for this long.
+pub const SPLIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum amount of payload carried by a single reliable split frame.
+pub const MAX_CHUNK_SIZE: usize = MAX_PACKET_SIZE - BASE_HEADER_SIZE - RELIABLE_HEADER_SIZE - SPLIT_HEADER_SIZE;
+
+struct IncompleteSplit {
+    chunk_count: u16,
+    /// Chunks are stored as they arrive, so memory use depends on how much
+    /// was received rather than on the claimed chunk count.
+    chunks: BTreeMap<u16, Vec<u8>>,
+    updated_at: Instant,
+}
+
+fn check_limit(what: &'static str, len: usize, limit: usize) -> Result<()> {
+    if len > limit {
+        return Err(Error::LimitExceeded { what, len, limit });
+    }
+
+    Ok(())
+}
+
+/// Reassembles packets sent as multiple `FrameType::Split` chunks.
+///
+/// Chunk counts and the amount of buffered data are bounded by the
+/// [`mtt_serialize::Limits`] in effect on the current thread.
+pub struct SplitBuffer {
+    incomplete: HashMap<u16, IncompleteSplit>,
+    /// Total size of the chunks in `incomplete`.
+    buffered: usize,
+}
+
+impl SplitBuffer {
+    pub fn new() -> Self {
+        Self {
+            incomplete: HashMap::new(),
+            buffered: 0,
+        }
+    }
+
+    /// Stores a chunk and returns the whole payload once all chunks of the
+    /// packet have arrived. Duplicate chunks are ignored.
+    pub fn insert(&mut self, split: &Split, data: &[u8], now: Instant) -> Result<Option<Vec<u8>>> {
+        let limits = mtt_serialize::limits();
+
+        if split.chunk_number >= split.chunk_count {
+            return Err(Error::InvalidSplitChunk {
+                seqnum: split.seqnum,
+                chunk_number: split.chunk_number,
+                chunk_count: split.chunk_count,
+            });
+        }
+        check_limit("split chunk count", split.chunk_count as usize, limits.max_split_chunks)?;
+
+        if !self.incomplete.contains_key(&split.seqnum) {
+            check_limit(
+                "incomplete split packets",
+                self.incomplete.len() + 1,
+                limits.max_incomplete_splits,
+            )?;
+        }
+
+        let incomplete = self.incomplete.entry(split.seqnum).or_insert_with(|| IncompleteSplit {
+            chunk_count: split.chunk_count,
+            chunks: BTreeMap::new(),
+            updated_at: now,
+        });
+
+        if incomplete.chunk_count != split.chunk_count {
+            return Err(Error::SplitChunkCountMismatch {
+                seqnum: split.seqnum,
+                expected: incomplete.chunk_count,
+                actual: split.chunk_count,
+            });
+        }
+
+        incomplete.updated_at = now;
+
+        if incomplete.chunks.contains_key(&split.chunk_number) {
+            return Ok(None);
+        }
+
+        check_limit(
+            "buffered split data",
+            self.buffered + data.len(),
+            limits.max_split_bytes,
+        )?;
+        incomplete.chunks.insert(split.chunk_number, data.to_vec());
+        self.buffered += data.len();
+
+        if incomplete.chunks.len() < incomplete.chunk_count as usize {
+            return Ok(None);
+        }
+
+        let incomplete = self.incomplete.remove(&split.seqnum).unwrap();
+        let payload: Vec<u8> = incomplete.chunks.into_values().flatten().collect();
+        self.buffered -= payload.len();
+
+        Ok(Some(payload))
+    }
+
+    /// When the least recently updated packet expires.
+    pub fn next_expiry(&self, timeout: Duration) -> Option<Instant> {
+        self.incomplete
+            .values()
+            .map(|incomplete| incomplete.updated_at + timeout)
+            .min()
+    }
+
+    /// Drops packets which haven't received any chunks for `timeout`.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) {
+        let buffered = &mut self.buffered;
+        self.incomplete.retain(|_, incomplete| {
+            let keep = now.duration_since(incomplete.updated_at) < timeout;
+            if !keep {
+                *buffered -= incomplete.chunks.values().map(Vec::len).sum::<usize>();
+            }
+            keep
+        });
+    }
+}
+
+/// Cuts `data` into chunks small enough to fit into a single datagram.
+pub fn split_payload(seqnum: u16, data: &[u8]) -> Result<Vec<(Split, &[u8])>> {
+    let chunk_count: u16 = data
+        .len()
+        .div_ceil(MAX_CHUNK_SIZE)
+        .try_into()
+        .map_err(|_| Error::PacketTooLarge(data.len()))?;
+
+    let chunks = data
+        .chunks(MAX_CHUNK_SIZE)
+        .enumerate()
+        .map(|(chunk_number, chunk)| {
+            let split = Split {
+                seqnum,
+                chunk_count,
+                chunk_number: chunk_number as u16,
+            };
+
+            (split, chunk)
+        })
+        .collect();
+
+    Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mtt_serialize::{with_limits, Limits};
+
+    fn split(seqnum: u16, chunk_number: u16, chunk_count: u16) -> Split {
+        Split {
+            seqnum,
+            chunk_count,
+            chunk_number,
+        }
+    }
+
+    #[test]
+    fn chunks_are_reassembled_in_order() {
+        let now = Instant::now();
+        let mut buffer = SplitBuffer::new();
+
+        assert_eq!(buffer.insert(&split(7, 2, 3), b"c", now).unwrap(), None);
+        assert_eq!(buffer.insert(&split(7, 0, 3), b"a", now).unwrap(), None);
+        assert_eq!(buffer.insert(&split(7, 0, 3), b"a", now).unwrap(), None);
+        assert_eq!(
+            buffer.insert(&split(7, 1, 3), b"b", now).unwrap(),
+            Some(b"abc".to_vec())
+        );
+        assert_eq!(buffer.buffered, 0);
+    }
+
+    #[test]
+    fn invalid_chunk_counts_are_rejected() {
+        let now = Instant::now();
+        let mut buffer = SplitBuffer::new();
+
+        assert!(matches!(
+            buffer.insert(&split(0, 0, 0), b"", now),
+            Err(Error::InvalidSplitChunk { .. })
+        ));
+        assert!(matches!(
+            buffer.insert(&split(0, 0, u16::MAX), b"", now),
+            Err(Error::LimitExceeded { .. })
+        ));
+        assert!(buffer.incomplete.is_empty());
+    }
+
+    #[test]
+    fn buffered_splits_are_bounded() {
+        let now = Instant::now();
+        let limits = Limits {
+            max_incomplete_splits: 2,
+            max_split_bytes: 8,
+            ..Limits::DEFAULT
+        };
+
+        with_limits(limits, || {
+            let mut buffer = SplitBuffer::new();
+            buffer.insert(&split(0, 0, 2), b"aaa", now).unwrap();
+            buffer.insert(&split(1, 0, 2), b"bbb", now).unwrap();
+
+            let error = buffer.insert(&split(2, 0, 2), b"c", now).unwrap_err();
+            assert!(matches!(error, Error::LimitExceeded { .. }));
+            let error = buffer.insert(&split(1, 1, 2), b"bbb", now).unwrap_err();
+            assert!(matches!(error, Error::LimitExceeded { .. }));
+
+            // Expired packets free up space
+            buffer.expire(now + SPLIT_TIMEOUT, SPLIT_TIMEOUT);
+            assert_eq!(buffer.buffered, 0);
+            buffer.insert(&split(2, 0, 2), b"c", now).unwrap();
+        });
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+const RESEND_TIMEOUT_INITIAL: Duration = Duration::from_millis(500);
+const RESEND_TIMEOUT_MIN: Duration = Duration::from_millis(100);
+const RESEND_TIMEOUT_MAX: Duration = Duration::from_secs(3);
+const RESEND_TIMEOUT_FACTOR: u32 = 4;
+
+/// Weight of the newest sample in smoothed values.
+const SMOOTHING: f32 = 0.1;
+
+/// Snapshot of connection quality.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    /// Smoothed round-trip time, if any reliable frame was acked yet.
+    pub rtt: Option<Duration>,
+    pub rtt_min: Option<Duration>,
+    pub rtt_max: Option<Duration>,
+    /// Smoothed fraction of reliable transmissions which were resends.
+    pub loss_rate: f32,
+    /// Number of reliable frames which were sent but not acked yet, across
+    /// all channels.
+    pub in_flight: usize,
+    pub resend_timeout: Duration,
+}
+
+pub(crate) struct StatsTracker {
+    rtt: Option<Duration>,
+    rtt_min: Option<Duration>,
+    rtt_max: Option<Duration>,
+    loss_rate: f32,
+}
+
+impl StatsTracker {
+    pub fn new() -> Self {
+        Self {
+            rtt: None,
+            rtt_min: None,
+            rtt_max: None,
+            loss_rate: 0.0,
+        }
+    }
+
+    pub fn report_rtt(&mut self, rtt: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(avg) => avg.mul_f32(1.0 - SMOOTHING) + rtt.mul_f32(SMOOTHING),
+            None => rtt,
+        });
+
+        self.rtt_min = Some(self.rtt_min.map_or(rtt, |min| min.min(rtt)));
+        self.rtt_max = Some(self.rtt_max.map_or(rtt, |max| max.max(rtt)));
+    }
+
+    pub fn report_transmission(&mut self, is_resend: bool) {
+        let sample = if is_resend { 1.0 } else { 0.0 };
+        self.loss_rate = self.loss_rate * (1.0 - SMOOTHING) + sample * SMOOTHING;
+    }
+
+    /// Time after which unacked frames are resent. Follows the average RTT,
+    /// so that slow links aren't flooded and lost frames on fast links are
+    /// noticed quickly.
+    pub fn resend_timeout(&self) -> Duration {
+        match self.rtt {
+            Some(rtt) => (rtt * RESEND_TIMEOUT_FACTOR).clamp(RESEND_TIMEOUT_MIN, RESEND_TIMEOUT_MAX),
+            None => RESEND_TIMEOUT_INITIAL,
+        }
+    }
+
+    pub fn snapshot(&self, in_flight: usize) -> Stats {
+        Stats {
+            rtt: self.rtt,
+            rtt_min: self.rtt_min,
+            rtt_max: self.rtt_max,
+            loss_rate: self.loss_rate,
+            in_flight,
+            resend_timeout: self.resend_timeout(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::FrameType;
+    use crate::reliable::ReliableSender;
+    use std::time::Instant;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn rtt_is_smoothed() {
+        let mut stats = StatsTracker::new();
+        assert_eq!(stats.snapshot(0).rtt, None);
+
+        stats.report_rtt(100 * MS);
+        assert_eq!(stats.snapshot(0).rtt, Some(100 * MS));
+
+        stats.report_rtt(200 * MS);
+        let snapshot = stats.snapshot(0);
+        let rtt = snapshot.rtt.unwrap();
+        assert!(rtt > 109 * MS && rtt < 111 * MS, "{rtt:?}");
+        assert_eq!(snapshot.rtt_min, Some(100 * MS));
+        assert_eq!(snapshot.rtt_max, Some(200 * MS));
+    }
+
+    #[test]
+    fn resend_timeout_follows_rtt_within_bounds() {
+        let timeout_for = |rtt: Duration| {
+            let mut stats = StatsTracker::new();
+            stats.report_rtt(rtt);
+            stats.resend_timeout()
+        };
+
+        assert_eq!(StatsTracker::new().resend_timeout(), RESEND_TIMEOUT_INITIAL);
+        assert_eq!(timeout_for(200 * MS), 800 * MS);
+        assert_eq!(timeout_for(10 * MS), RESEND_TIMEOUT_MIN);
+        assert_eq!(timeout_for(2000 * MS), RESEND_TIMEOUT_MAX);
+    }
+
+    #[test]
+    fn resent_frames_dont_produce_rtt_samples() {
+        let start = Instant::now();
+        let timeout = 500 * MS;
+        let mut sender = ReliableSender::new(0);
+        sender.push(FrameType::Original, vec![]);
+        sender.push(FrameType::Original, vec![]);
+
+        sender.poll_transmit(start, timeout);
+        sender.ack(1, start + 50 * MS);
+        let resent = sender.poll_transmit(start + timeout, timeout);
+        assert_eq!(resent.len(), 1);
+
+        // Ack might belong to either transmission, so it's ignored
+        assert_eq!(sender.ack(0, start + timeout + 50 * MS), None);
+    }
+
+    #[test]
+    fn loss_rate_counts_resends() {
+        let mut stats = StatsTracker::new();
+        stats.report_transmission(false);
+        assert_eq!(stats.snapshot(0).loss_rate, 0.0);
+
+        stats.report_transmission(true);
+        assert!((stats.snapshot(0).loss_rate - SMOOTHING).abs() < 1e-6);
+    }
+}
@@ -1,7 +1,261 @@
-use anyhow::Result;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use glam::{Vec3, vec3, i16vec3, I16Vec3};
-use std::io::{Read, Write};
+use std::cell::Cell;
+use std::io::{self, Cursor, Read, Write};
+use std::string::{FromUtf16Error, FromUtf8Error};
+use std::thread::LocalKey;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Data ended before the value was complete.
+    #[error("unexpected end of data")]
+    Truncated,
+    #[error(transparent)]
+    Io(io::Error),
+    #[error("invalid UTF-8 string")]
+    InvalidUtf8(#[from] FromUtf8Error),
+    #[error("invalid UTF-16 string")]
+    InvalidUtf16(#[from] FromUtf16Error),
+    #[error("{what} is too long to serialize ({len})")]
+    TooLong {
+        what: &'static str,
+        len: usize,
+    },
+    #[error("unknown {what}: {value}")]
+    UnknownVariant {
+        what: &'static str,
+        value: u64,
+    },
+    #[error("unsupported {what} version: {version}")]
+    UnsupportedVersion {
+        what: &'static str,
+        version: u64,
+    },
+    #[error("invalid {what} (expected {expected}, got {actual})")]
+    Unexpected {
+        what: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("invalid {what}")]
+    Invalid {
+        what: &'static str,
+    },
+    /// The value doesn't keep enough information to be written back.
+    #[error("serializing {what} isn't supported")]
+    Unsupported {
+        what: &'static str,
+    },
+    #[error("{what} exceeds the limit ({len} > {limit})")]
+    LimitExceeded {
+        what: &'static str,
+        len: usize,
+        limit: usize,
+    },
+    #[error("unknown packet ID: {0:#04x}")]
+    UnknownPacketId(u16),
+    #[error("decompression failed: {0}")]
+    Decompression(io::Error),
+    #[error("in field `{field}`: {error}")]
+    Field {
+        field: &'static str,
+        error: Box<Error>,
+    },
+    #[error("in packet {name}: {error}")]
+    Packet {
+        name: &'static str,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    pub fn field(field: &'static str, error: Error) -> Self {
+        Error::Field {
+            field,
+            error: Box::new(error),
+        }
+    }
+
+    pub fn packet(name: &'static str, error: Error) -> Self {
+        Error::Packet {
+            name,
+            error: Box::new(error),
+        }
+    }
+
+    /// Returns the underlying error, without field and packet context.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Field { error, .. } | Error::Packet { error, .. } => error.root(),
+            error => error,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => Error::Io(error),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Upper bounds on what a single value may make the reader allocate, so that
+/// lengths read from untrusted input can't exhaust memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Maximum length of a string or a byte array, in bytes.
+    pub max_bytes: usize,
+    /// Maximum number of elements in a `Vec` or an inventory list. Leaves
+    /// room for creative inventories of large games, which list every item.
+    pub max_elements: usize,
+    /// Maximum size of decompressed data.
+    pub max_decompressed: usize,
+    /// Maximum number of chunks a split packet may consist of.
+    pub max_split_chunks: usize,
+    /// Maximum number of split packets per channel which are still missing
+    /// chunks.
+    pub max_incomplete_splits: usize,
+    /// Maximum number of bytes buffered for incomplete split packets, per
+    /// channel.
+    pub max_split_bytes: usize,
+}
+
+impl Limits {
+    pub const DEFAULT: Limits = Limits {
+        max_bytes: 64 * 1024 * 1024,
+        max_elements: 16 * 1024,
+        max_decompressed: 64 * 1024 * 1024,
+        max_split_chunks: 8192,
+        max_incomplete_splits: 64,
+        max_split_bytes: 16 * 1024 * 1024,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits::DEFAULT
+    }
+}
+
+/// Protocol and serialization versions the data was produced with. Parsers
+/// whose layout changed between versions check these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Versions {
+    pub protocol: u16,
+    pub serialization: u8,
+}
+
+impl Versions {
+    /// Assumes the newest layout of everything.
+    pub const LATEST: Versions = Versions {
+        protocol: u16::MAX,
+        serialization: u8::MAX,
+    };
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        Versions::LATEST
+    }
+}
+
+thread_local! {
+    static LIMITS: Cell<Limits> = const { Cell::new(Limits::DEFAULT) };
+    static VERSIONS: Cell<Versions> = const { Cell::new(Versions::LATEST) };
+}
+
+/// Replaces a thread-local value while `f` runs.
+fn with_local<V: Copy + 'static, T>(key: &'static LocalKey<Cell<V>>, value: V, f: impl FnOnce() -> T) -> T {
+    struct Restore<V: Copy + 'static>(&'static LocalKey<Cell<V>>, V);
+
+    impl<V: Copy + 'static> Drop for Restore<V> {
+        fn drop(&mut self) {
+            self.0.with(|cell| cell.set(self.1));
+        }
+    }
+
+    let _restore = Restore(key, key.with(|cell| cell.replace(value)));
+
+    f()
+}
+
+/// Returns limits in effect on the current thread.
+pub fn limits() -> Limits {
+    LIMITS.with(Cell::get)
+}
+
+/// Runs `f` with `limits` applied to everything deserialized on the current
+/// thread, restoring the previous limits afterwards.
+pub fn with_limits<T>(limits: Limits, f: impl FnOnce() -> T) -> T {
+    with_local(&LIMITS, limits, f)
+}
+
+/// Returns versions in effect on the current thread.
+pub fn versions() -> Versions {
+    VERSIONS.with(Cell::get)
+}
+
+/// Runs `f` with `versions` applied to everything serialized and
+/// deserialized on the current thread.
+pub fn with_versions<T>(versions: Versions, f: impl FnOnce() -> T) -> T {
+    with_local(&VERSIONS, versions, f)
+}
+
+fn check_limit(what: &'static str, len: usize, limit: usize) -> Result<()> {
+    if len > limit {
+        return Err(Error::LimitExceeded { what, len, limit });
+    }
+
+    Ok(())
+}
+
+/// Reads exactly `len` bytes. Memory is allocated as data arrives, rather
+/// than upfront, so a bogus length fails on missing data instead.
+pub fn read_bytes<R: Read>(r: &mut R, len: usize) -> Result<Vec<u8>> {
+    check_limit("byte array", len, limits().max_bytes)?;
+
+    let mut data = Vec::new();
+    r.take(len as u64).read_to_end(&mut data)?;
+
+    if data.len() < len {
+        return Err(Error::Truncated);
+    }
+
+    Ok(data)
+}
+
+/// Reads a trailing field which older versions don't send, unless the data
+/// ends before it.
+pub fn deserialize_optional<T: Serialize, D: AsRef<[u8]>>(r: &mut Cursor<D>) -> Result<Option<T>> {
+    if r.position() >= r.get_ref().as_ref().len() as u64 {
+        return Ok(None);
+    }
+
+    T::deserialize(r).map(Some)
+}
+
+/// Reads everything from a decompressing reader, failing once the output
+/// grows past the limit.
+pub fn decompress<R: Read>(decoder: R) -> Result<Vec<u8>> {
+    let limit = limits().max_decompressed;
+
+    let mut data = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .map_err(Error::Decompression)?;
+
+    check_limit("decompressed data", data.len(), limit)?;
+
+    Ok(data)
+}
+
+/// `Vec` capacity reserved upfront is capped at this many elements.
+const MAX_PREALLOCATED_ELEMENTS: usize = 1024;
 
 pub trait Serialize: Sized {
     fn serialize<W: Write>(&self, w: &mut W) -> Result<()>;
@@ -60,7 +314,9 @@ impl Serialize for bool {
 impl Serialize for String {
     fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
         let len = self.len();
-        assert!(len < u16::MAX as usize);
+        if len >= u16::MAX as usize {
+            return Err(Error::TooLong { what: "string", len });
+        }
         (len as u16).serialize(w)?;
         w.write_all(self.as_bytes())?;
         Ok(())
@@ -68,24 +324,36 @@ impl Serialize for String {
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let len = u16::deserialize(r)? as usize;
-        let mut data = vec![0; len];
-        r.read_exact(&mut data)?;
+        let data = read_bytes(r, len)?;
         Ok(String::from_utf8(data)?)
     }
 }
 
 pub trait StringSerializeExt: Sized {
+    fn serialize_utf16<W: Write>(&self, w: &mut W) -> Result<()>;
     fn deserialize_utf16<R: Read>(r: &mut R) -> Result<Self>;
 }
 
 impl StringSerializeExt for String {
+    fn serialize_utf16<W: Write>(&self, w: &mut W) -> Result<()> {
+        let data: Vec<u16> = self.encode_utf16().collect();
+        let len = data.len();
+        if len >= u16::MAX as usize {
+            return Err(Error::TooLong { what: "string", len });
+        }
+        (len as u16).serialize(w)?;
+        for value in data {
+            value.serialize(w)?;
+        }
+        Ok(())
+    }
+
     fn deserialize_utf16<R: Read>(r: &mut R) -> Result<Self> {
         let len = u16::deserialize(r)? as usize;
-        let mut data = Vec::with_capacity(len);
-
-        for _ in 0..len {
-            data.push(u16::deserialize(r)?);
-        }
+        let data: Vec<u16> = read_bytes(r, len * 2)?
+            .chunks_exact(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect();
 
         Ok(String::from_utf16(&data)?)
     }
@@ -94,7 +362,9 @@ impl StringSerializeExt for String {
 impl<T: Serialize> Serialize for Vec<T> {
     fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
         let len = self.len();
-        anyhow::ensure!(len < u16::MAX as usize, "vec is too long to serialize");
+        if len >= u16::MAX as usize {
+            return Err(Error::TooLong { what: "vec", len });
+        }
         (len as u16).serialize(w)?;
         for value in self {
             value.serialize(w)?;
@@ -104,7 +374,10 @@ impl<T: Serialize> Serialize for Vec<T> {
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let len = u16::deserialize(r)? as usize;
-        let mut values = Vec::with_capacity(len);
+        check_limit("vec", len, limits().max_elements)?;
+
+        // Elements may be much larger in memory than on the wire
+        let mut values = Vec::with_capacity(len.min(MAX_PREALLOCATED_ELEMENTS));
         for _ in 0..len {
             values.push(T::deserialize(r)?);
         }
@@ -130,7 +403,12 @@ impl From<Vec<u8>> for RawBytes16 {
 impl Serialize for RawBytes16 {
     fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
         let len = self.0.len();
-        assert!(len < u16::MAX as usize);
+        if len >= u16::MAX as usize {
+            return Err(Error::TooLong {
+                what: "byte array",
+                len,
+            });
+        }
         (len as u16).serialize(w)?;
         w.write_all(&self.0)?;
         Ok(())
@@ -138,9 +416,7 @@ impl Serialize for RawBytes16 {
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let len = u16::deserialize(r)? as usize;
-        let mut data = vec![0; len];
-        r.read_exact(&mut data)?;
-        Ok(Self(data))
+        Ok(Self(read_bytes(r, len)?))
     }
 }
 
@@ -156,7 +432,12 @@ impl From<RawBytes32> for Vec<u8> {
 impl Serialize for RawBytes32 {
     fn serialize<W: Write>(&self, w: &mut W) -> Result<()> {
         let len = self.0.len();
-        assert!(len < u32::MAX as usize);
+        if len >= u32::MAX as usize {
+            return Err(Error::TooLong {
+                what: "byte array",
+                len,
+            });
+        }
         (len as u32).serialize(w)?;
         w.write_all(&self.0)?;
         Ok(())
@@ -164,9 +445,7 @@ impl Serialize for RawBytes32 {
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
         let len = u32::deserialize(r)? as usize;
-        let mut data = vec![0; len];
-        r.read_exact(&mut data)?;
-        Ok(Self(data))
+        Ok(Self(read_bytes(r, len)?))
     }
 }
 
@@ -186,8 +465,12 @@ impl Serialize for RawBytesUnsized {
     }
 
     fn deserialize<R: Read>(r: &mut R) -> Result<Self> {
+        let limit = limits().max_bytes;
+
         let mut data = Vec::new();
-        r.read_to_end(&mut data)?;
+        r.take(limit as u64 + 1).read_to_end(&mut data)?;
+        check_limit("byte array", data.len(), limit)?;
+
         Ok(Self(data))
     }
 }
@@ -228,3 +511,86 @@ impl Serialize for I16Vec3 {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: Limits = Limits {
+        max_bytes: 16,
+        max_elements: 4,
+        max_decompressed: 1024,
+        ..Limits::DEFAULT
+    };
+
+    fn assert_limit_exceeded<T: std::fmt::Debug>(result: Result<T>, expected: &'static str) {
+        match result {
+            Err(Error::LimitExceeded { what, .. }) => assert_eq!(what, expected),
+            result => panic!("expected {expected} to exceed the limit, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected_before_reading() {
+        // Only the length prefix is there, so reading anything would fail
+        // with `Truncated` instead
+        with_limits(SMALL, || {
+            assert_limit_exceeded(String::deserialize(&mut Cursor::new([0xFF, 0xFF])), "byte array");
+            assert_limit_exceeded(RawBytes16::deserialize(&mut Cursor::new([0, 17])), "byte array");
+            assert_limit_exceeded(Vec::<u8>::deserialize(&mut Cursor::new([0, 5])), "vec");
+        });
+
+        let data = u32::MAX.to_be_bytes();
+        assert_limit_exceeded(RawBytes32::deserialize(&mut Cursor::new(data)), "byte array");
+
+        // Lengths within the limit still need the data
+        let result = with_limits(SMALL, || String::deserialize(&mut Cursor::new([0, 16])));
+        assert!(matches!(result, Err(Error::Truncated)));
+    }
+
+    #[test]
+    fn decompression_bombs_are_rejected() {
+        let data = vec![0; 1024 * 1024];
+
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&data).unwrap();
+        let zlib = encoder.finish().unwrap();
+
+        let zstd = zstd::encode_all(data.as_slice(), 19).unwrap();
+
+        with_limits(SMALL, || {
+            let decoder = flate2::read::ZlibDecoder::new(zlib.as_slice());
+            assert_limit_exceeded(decompress(decoder), "decompressed data");
+
+            let decoder = zstd::Decoder::new(zstd.as_slice()).unwrap();
+            assert_limit_exceeded(decompress(decoder), "decompressed data");
+        });
+
+        let decoder = flate2::read::ZlibDecoder::new(zlib.as_slice());
+        assert_eq!(decompress(decoder).unwrap(), data);
+    }
+
+    #[test]
+    fn with_limits_restores_previous_limits() {
+        assert_eq!(limits().max_bytes, Limits::DEFAULT.max_bytes);
+
+        let result = with_limits(SMALL, || {
+            assert_eq!(limits().max_bytes, SMALL.max_bytes);
+
+            let unlimited = Limits {
+                max_bytes: usize::MAX,
+                ..SMALL
+            };
+            with_limits(unlimited, || assert_eq!(limits().max_bytes, usize::MAX));
+            assert_eq!(limits().max_bytes, SMALL.max_bytes);
+
+            String::deserialize(&mut Cursor::new([0xFF, 0xFF]))
+        });
+        assert!(result.is_err());
+        assert_eq!(limits().max_bytes, Limits::DEFAULT.max_bytes);
+
+        let result = std::panic::catch_unwind(|| with_limits(SMALL, || panic!("decoder panicked")));
+        assert!(result.is_err());
+        assert_eq!(limits().max_bytes, Limits::DEFAULT.max_bytes);
+    }
+}